@@ -0,0 +1,12 @@
+CREATE TABLE visits (
+    id BIGSERIAL PRIMARY KEY,
+    project_name TEXT NOT NULL,
+    platform TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    country TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_visits_project_name ON visits(project_name);
+CREATE INDEX idx_visits_platform ON visits(platform);
+CREATE INDEX idx_visits_created_at ON visits(created_at);
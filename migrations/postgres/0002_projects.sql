@@ -0,0 +1,35 @@
+CREATE TABLE projects (
+    slug TEXT PRIMARY KEY,
+    repository TEXT NOT NULL,
+    icon TEXT NOT NULL,
+    description TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    archived_at TIMESTAMPTZ
+);
+
+INSERT INTO projects (slug, repository, icon, description) VALUES
+    (
+        'dwall',
+        'https://github.com/dwall-rs/dwall',
+        'https://raw.githubusercontent.com/dwall-rs/dwall/refs/heads/main/src-tauri/icons/icon.ico',
+        '在 Windows 中模拟 macOS 根据时间切换壁纸的程序'
+    ),
+    (
+        'lsar',
+        'https://github.com/alley-rs/lsar',
+        'https://raw.githubusercontent.com/alley-rs/lsar/refs/heads/main/src-tauri/icons/icon.ico',
+        '聚合多个平台的直播解析程序，目前支持斗鱼、虎牙、抖音、B站、Bigo'
+    ),
+    (
+        'up2b',
+        'https://github.com/up2b/up2b',
+        'https://raw.githubusercontent.com/up2b/up2b/refs/heads/main/src-tauri/icons/icon.ico',
+        '支持多个图床的图床管理程序'
+    ),
+    (
+        'fluxy',
+        'https://github.com/alley-rs/fluxy',
+        'https://raw.githubusercontent.com/alley-rs/fluxy/refs/heads/main/src-tauri/icons/icon.ico',
+        '轻量、快速的文件传输工具'
+    );
+
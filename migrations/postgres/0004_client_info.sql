@@ -0,0 +1,6 @@
+-- 客户端上报的应用版本、系统版本和架构，旧版本客户端不上报时为空
+ALTER TABLE visits ADD COLUMN app_version TEXT;
+ALTER TABLE visits ADD COLUMN os_version TEXT;
+ALTER TABLE visits ADD COLUMN arch TEXT;
+
+CREATE INDEX idx_visits_app_version ON visits(project_name, app_version);
@@ -0,0 +1,2 @@
+-- 客户端生成的安装标识，只保存哈希值，用于统计独立用户
+ALTER TABLE visits ADD COLUMN install_id TEXT;
@@ -0,0 +1,17 @@
+-- 客户端上报的自定义事件，如 `wallpaper_changed`、`upload_failed`
+CREATE TABLE events (
+    id BIGSERIAL PRIMARY KEY,
+    project_name TEXT NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_events_project_event_created_at ON events(project_name, name, created_at);
+
+-- 事件的属性，每个属性一行，值统一保存为文本
+CREATE TABLE event_properties (
+    event_id BIGINT NOT NULL REFERENCES events(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (event_id, key)
+);
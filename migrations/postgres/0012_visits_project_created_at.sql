@@ -0,0 +1,3 @@
+-- 统计查询总是按项目筛选后再限制时间范围，复合索引同时覆盖只按项目筛选的查询
+CREATE INDEX idx_visits_project_created_at ON visits(project_name, created_at);
+DROP INDEX IF EXISTS idx_visits_project_name;
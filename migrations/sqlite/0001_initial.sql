@@ -0,0 +1,13 @@
+-- 与引入迁移之前的数据库结构一致，已有数据库可以直接记录为该版本
+CREATE TABLE IF NOT EXISTS visits (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    project_name TEXT NOT NULL,
+    platform TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    country TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_visits_project_name ON visits(project_name);
+CREATE INDEX IF NOT EXISTS idx_visits_platform ON visits(platform);
+CREATE INDEX IF NOT EXISTS idx_visits_created_at ON visits(created_at);
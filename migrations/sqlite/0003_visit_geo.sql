@@ -0,0 +1,5 @@
+ALTER TABLE visits ADD COLUMN region TEXT;
+ALTER TABLE visits ADD COLUMN city TEXT;
+
+-- 后台任务按该索引查找尚未补充地理位置的访问记录
+CREATE INDEX idx_visits_pending_geo ON visits(id) WHERE country IS NULL;
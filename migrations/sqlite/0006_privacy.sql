@@ -0,0 +1,8 @@
+-- 匿名化保存 IP 地址时，原始地址暂存于此，补充地理位置后清除
+ALTER TABLE visits ADD COLUMN pending_ip TEXT;
+
+-- 计算 IP 地址哈希使用的盐，每天（UTC）一个
+CREATE TABLE ip_salts (
+    day TEXT PRIMARY KEY,
+    salt TEXT NOT NULL
+);
@@ -0,0 +1,15 @@
+-- 原始访问记录的保留天数，为空时永久保留
+ALTER TABLE projects ADD COLUMN retention_days INTEGER;
+
+-- 超过保留期限的访问记录按天（UTC）汇总到此表，原始记录随后删除
+CREATE TABLE daily_visits (
+    project_name TEXT NOT NULL,
+    day TIMESTAMP NOT NULL,
+    platform TEXT NOT NULL,
+    country TEXT,
+    app_version TEXT,
+    visit_count INTEGER NOT NULL,
+    unique_visitors INTEGER NOT NULL
+);
+
+CREATE INDEX idx_daily_visits_project_day ON daily_visits(project_name, day);
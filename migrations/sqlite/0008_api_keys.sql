@@ -0,0 +1,10 @@
+-- 读取统计接口使用的 API key，只保存哈希值
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    -- 只能读取该项目的统计，为空时可以读取所有项目
+    project_name TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMP
+);
@@ -0,0 +1,8 @@
+-- 因限流或去重未计入的访问次数，按天（UTC）累计
+CREATE TABLE dropped_visits (
+    project_name TEXT NOT NULL,
+    day TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    hit_count INTEGER NOT NULL,
+    PRIMARY KEY (project_name, day, reason)
+);
@@ -0,0 +1,9 @@
+-- 之前的版本将回环地址、192.168.0.0/16 和未知的客户端地址统一记为 `Local`，
+-- 按保存的地址区分为 `Loopback`、`Private` 和 `Unknown`，地址已哈希或已按天汇总的记录保持不变
+UPDATE visits SET country = CASE
+        WHEN ip_address LIKE '127.%' THEN 'Loopback'
+        WHEN ip_address LIKE '192.168.%' THEN 'Private'
+        WHEN ip_address = 'unknown' THEN 'Unknown'
+        ELSE country
+    END
+WHERE country = 'Local';
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::database;
+use crate::models::ProjectSlug;
+use crate::privacy;
+use crate::{
+    error::AppError,
+    state::{AdminToken, StatsAuth},
+};
+
+/// API key 的前缀，便于在日志或配置中识别
+const API_KEY_PREFIX: &str = "pt_";
+
+/// 校验管理接口的 `Authorization: Bearer <token>` 请求头
+pub async fn require_admin(
+    State(AdminToken(token)): State<AdminToken>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    match bearer_token(&headers) {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => {
+            warn!("管理接口鉴权失败");
+            Err(AppError::Unauthorized)
+        }
+    }
+}
+
+/// 校验统计接口的 `Authorization: Bearer <key>` 请求头
+///
+/// 管理令牌可以读取所有统计；限定了项目的 API key 只能读取该项目的统计，不能读取所有项目的汇总
+pub async fn require_api_key(
+    State(auth): State<StatsAuth>,
+    params: Option<Path<HashMap<String, String>>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(provided) = bearer_token(&headers) else {
+        warn!("统计接口鉴权失败: 未提供凭证");
+        return Err(AppError::Unauthorized);
+    };
+
+    if let Some(AdminToken(token)) = &auth.admin_token
+        && constant_time_eq(provided.as_bytes(), token.as_bytes())
+    {
+        return Ok(next.run(request).await);
+    }
+
+    let Some(key) = database::find_api_key(&auth.pool, &hash_api_key(provided)).await? else {
+        warn!("统计接口鉴权失败: API key 无效");
+        return Err(AppError::Unauthorized);
+    };
+
+    // 项目标识不正确时，不限项目的 key 交给处理函数返回对应的错误
+    let project_name = params.and_then(|Path(mut params)| params.remove("project_name"));
+    let project = match project_name {
+        Some(name) => match name.parse::<ProjectSlug>() {
+            Ok(slug) => Some(slug),
+            Err(_) if key.project_name.is_none() => None,
+            Err(_) => return Err(AppError::Forbidden),
+        },
+        None => None,
+    };
+    if !key.allows(project.as_ref()) {
+        warn!("API key `{}` 无权访问请求的统计", key.name);
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// 生成新的 API key，返回 key 本身及其哈希值，数据库中只保存哈希值
+pub fn generate_api_key() -> (String, String) {
+    let key = format!("{}{}", API_KEY_PREFIX, privacy::random_hex(24));
+    let hash = hash_api_key(&key);
+
+    (key, hash)
+}
+
+fn hash_api_key(key: &str) -> String {
+    privacy::sha256_hex(key.as_bytes())
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// 比较耗时与内容无关，避免通过响应时间猜测令牌
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,490 @@
+//! 确定客户端 IP 地址，只采信受信任代理添加的转发请求头，使用哪个请求头由配置决定
+
+use std::{
+    convert::Infallible,
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{HeaderMap, HeaderName, header, request::Parts},
+};
+use serde::Deserialize;
+
+/// IP 地址段，如 `10.0.0.0/8`、`fd00::/8`，省略前缀长度时只包含单个地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(u32::from(net).into(), u32::from(ip).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `a` 和 `b` 的前 `prefix` 位是否相同，`bits` 为地址的总位数
+fn prefix_eq(a: u128, b: u128, bits: u8, prefix: u8) -> bool {
+    prefix == 0 || (a ^ b) >> (bits - prefix) == 0
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let written = addr
+            .parse::<IpAddr>()
+            .map_err(|_| format!("invalid ip address `{}`", addr))?;
+        let addr = written.to_canonical();
+        let bits = if written.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|&p| p <= bits)
+                .ok_or_else(|| format!("invalid prefix length in `{}`", s))?,
+            None => bits,
+        };
+        // `::ffff:a.b.c.d/n` 按 IPv4 地址段处理，前缀长度扣除映射部分的 96 位
+        let prefix = match (written, addr) {
+            (IpAddr::V6(_), IpAddr::V4(_)) => prefix
+                .checked_sub(96)
+                .ok_or_else(|| format!("invalid prefix length in `{}`", s))?,
+            _ => prefix,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// 受信任的代理写入客户端地址的请求头
+///
+/// 只读取配置的请求头，其他转发请求头可能由客户端伪造后被代理原样转发
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardedHeader {
+    /// RFC 7239 `Forwarded` 中的 `for=` 参数
+    Forwarded,
+    /// `X-Forwarded-For`，如 nginx 的 `$proxy_add_x_forwarded_for`
+    #[default]
+    XForwardedFor,
+    /// `X-Real-IP`，只包含一个地址
+    XRealIp,
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "forwarded" => Ok(ForwardedHeader::Forwarded),
+            "x-forwarded-for" => Ok(ForwardedHeader::XForwardedFor),
+            "x-real-ip" => Ok(ForwardedHeader::XRealIp),
+            _ => Err(format!("unknown forwarded header `{}`", s)),
+        }
+    }
+}
+
+/// 受信任的代理，只有来自这些地址的转发请求头才会被采信
+#[derive(Debug, Clone)]
+pub struct TrustedProxies {
+    proxies: Arc<[Cidr]>,
+    header: ForwardedHeader,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: &[Cidr], header: ForwardedHeader) -> Self {
+        Self {
+            proxies: proxies.into(),
+            header,
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// 从连接的对端地址开始，从右向左逐跳检查转发链，返回第一个不受信任的地址
+    ///
+    /// `peer` 为 `None` 表示通过 Unix 域套接字连接，对端是本机的代理，视为受信任
+    fn resolve(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer.map(|ip| ip.to_canonical());
+        if let Some(ip) = peer
+            && !self.contains(ip)
+        {
+            return Some(ip);
+        }
+
+        let hops = match self.header {
+            ForwardedHeader::Forwarded => forwarded_for(headers),
+            ForwardedHeader::XForwardedFor => x_forwarded_for(headers),
+            ForwardedHeader::XRealIp => return real_ip(headers).or(peer),
+        };
+        let Some(hops) = hops else {
+            return peer;
+        };
+
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            // 无法解析的一跳（如 `unknown`、`_hidden`）之前的地址都无法确认
+            let ip = hop?;
+            client = Some(ip);
+            if !self.contains(ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// 按 RFC 7239 解析所有 `Forwarded` 请求头中的 `for=` 参数，没有该请求头时返回 `None`
+fn forwarded_for(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let values = header_values(headers, &header::FORWARDED)?;
+    let hops = values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .map(|node| node.and_then(parse_node))
+        .collect();
+
+    Some(hops)
+}
+
+/// 解析所有 `X-Forwarded-For` 请求头，没有该请求头时返回 `None`
+fn x_forwarded_for(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let values = header_values(headers, &HeaderName::from_static("x-forwarded-for"))?;
+    let hops = values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(parse_node)
+        .collect();
+
+    Some(hops)
+}
+
+fn real_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get(HeaderName::from_static("x-real-ip"))?
+        .to_str()
+        .ok()
+        .and_then(parse_node)
+}
+
+/// 同名请求头的所有值，没有该请求头时返回 `None`
+///
+/// 任一值不是合法的字符串时按只有一个无法识别的节点处理，转发链在此中断
+fn header_values<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<Vec<&'a str>> {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>();
+
+    match values {
+        Some(values) if !values.is_empty() => Some(values),
+        Some(_) => None,
+        None => Some(vec![""]),
+    }
+}
+
+/// 解析转发链中的节点，支持带引号、带端口及方括号包围的 IPv6 地址，
+/// 如 `192.0.2.43`、`"192.0.2.43:47011"`、`"[2001:db8::17]:4711"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    let ip = if let Some(rest) = node.strip_prefix('[') {
+        rest.split_once(']')?.0.parse().ok()?
+    } else if let Ok(ip) = node.parse::<IpAddr>() {
+        ip
+    } else {
+        let (host, _port) = node.rsplit_once(':')?;
+        IpAddr::V4(host.parse::<Ipv4Addr>().ok()?)
+    };
+
+    Some(ip.to_canonical())
+}
+
+/// 客户端 IP 地址，无法确定时为 `None`，显示为 `unknown`
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(ip) => write!(f, "{}", ip),
+            None => f.write_str("unknown"),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    TrustedProxies: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        Ok(Self(
+            TrustedProxies::from_ref(state).resolve(peer, &parts.headers),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    const PROXIES: &[&str] = &["10.0.0.0/8", "2001:db8:ffff::/48"];
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn resolve(
+        header: ForwardedHeader,
+        peer: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> Option<IpAddr> {
+        let proxies: Vec<Cidr> = PROXIES.iter().map(|p| p.parse().unwrap()).collect();
+        let mut map = HeaderMap::new();
+        for &(name, value) in headers {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        TrustedProxies::new(&proxies, header).resolve(peer.map(ip), &map)
+    }
+
+    #[test]
+    fn parses_cidrs() {
+        let cidr = |s: &str| s.parse::<Cidr>();
+        assert!(cidr("10.0.0.0/8").unwrap().contains(ip("10.255.0.1")));
+        assert!(!cidr("10.0.0.0/8").unwrap().contains(ip("11.0.0.1")));
+        assert!(cidr("10.0.0.1").unwrap().contains(ip("::ffff:10.0.0.1")));
+        assert!(!cidr("10.0.0.1").unwrap().contains(ip("10.0.0.2")));
+        assert!(
+            cidr("::ffff:10.0.0.0/104")
+                .unwrap()
+                .contains(ip("10.0.0.1"))
+        );
+        assert!(cidr("0.0.0.0/0").unwrap().contains(ip("203.0.113.9")));
+        assert!(!cidr("0.0.0.0/0").unwrap().contains(ip("2001:db8::1")));
+        assert!(cidr("::/0").unwrap().contains(ip("2001:db8::1")));
+        for invalid in [
+            "10.0.0.0/33",
+            "::/129",
+            "::ffff:10.0.0.0/95",
+            "10.0.0.0/",
+            "10.0.0/8",
+            "example.com",
+        ] {
+            assert!(cidr(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn walks_x_forwarded_for_from_the_right() {
+        use ForwardedHeader::XForwardedFor as Xff;
+        const XFF: &str = "x-forwarded-for";
+
+        for (peer, headers, expected) in [
+            // 对端不受信任时忽略转发请求头
+            (
+                Some("203.0.113.9"),
+                &[(XFF, "1.2.3.4")][..],
+                Some("203.0.113.9"),
+            ),
+            (Some("::ffff:203.0.113.9"), &[], Some("203.0.113.9")),
+            (Some("10.0.0.1"), &[], Some("10.0.0.1")),
+            (Some("10.0.0.1"), &[(XFF, "1.2.3.4")], Some("1.2.3.4")),
+            (
+                Some("::ffff:10.0.0.1"),
+                &[(XFF, "1.2.3.4")],
+                Some("1.2.3.4"),
+            ),
+            // 客户端伪造的地址在最左侧，取最右侧的不受信任地址
+            (
+                Some("10.0.0.1"),
+                &[(XFF, "6.6.6.6, 1.2.3.4, 10.0.0.2")],
+                Some("1.2.3.4"),
+            ),
+            (
+                Some("10.0.0.1"),
+                &[(XFF, "6.6.6.6"), (XFF, "1.2.3.4, 10.0.0.2")],
+                Some("1.2.3.4"),
+            ),
+            (
+                Some("10.0.0.1"),
+                &[(XFF, "2001:db8::1, 2001:db8:ffff::2")],
+                Some("2001:db8::1"),
+            ),
+            // 整条链都受信任时取最左侧的地址
+            (
+                Some("10.0.0.1"),
+                &[(XFF, "10.0.0.3, 10.0.0.2")],
+                Some("10.0.0.3"),
+            ),
+            (Some("10.0.0.1"), &[(XFF, "1.2.3.4, unknown")], None),
+            (
+                Some("10.0.0.1"),
+                &[(XFF, "unknown, 1.2.3.4")],
+                Some("1.2.3.4"),
+            ),
+            (Some("10.0.0.1"), &[(XFF, "")], None),
+            (Some("10.0.0.1"), &[(XFF, "1.2.3.4:5678")], Some("1.2.3.4")),
+            (
+                Some("10.0.0.1"),
+                &[(XFF, "[2001:db8::1]:443")],
+                Some("2001:db8::1"),
+            ),
+            // 只读取配置的请求头
+            (
+                Some("10.0.0.1"),
+                &[("x-real-ip", "1.2.3.4")],
+                Some("10.0.0.1"),
+            ),
+            (
+                Some("10.0.0.1"),
+                &[("forwarded", "for=1.2.3.4")],
+                Some("10.0.0.1"),
+            ),
+            // Unix 域套接字的对端视为受信任
+            (None, &[(XFF, "1.2.3.4")], Some("1.2.3.4")),
+            (None, &[], None),
+        ] {
+            assert_eq!(
+                resolve(Xff, peer, headers),
+                expected.map(ip),
+                "{peer:?} {headers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn walks_forwarded_from_the_right() {
+        use ForwardedHeader::Forwarded;
+        const FWD: &str = "forwarded";
+
+        for (headers, expected) in [
+            (
+                &[(FWD, "for=192.0.2.60;proto=http;by=203.0.113.43")][..],
+                Some("192.0.2.60"),
+            ),
+            (
+                &[(FWD, r#"for="[2001:db8:cafe::17]:4711""#)],
+                Some("2001:db8:cafe::17"),
+            ),
+            (&[(FWD, r#"for="192.0.2.43:47011""#)], Some("192.0.2.43")),
+            (
+                &[(FWD, "For=6.6.6.6, for=1.2.3.4, for=10.0.0.2")],
+                Some("1.2.3.4"),
+            ),
+            (
+                &[(FWD, "for=6.6.6.6"), (FWD, "for=1.2.3.4;proto=https")],
+                Some("1.2.3.4"),
+            ),
+            // 隐藏或无法识别的节点之前的地址都无法确认
+            (&[(FWD, "for=1.2.3.4, for=_hidden")], None),
+            (&[(FWD, "for=1.2.3.4, for=unknown, for=10.0.0.2")], None),
+            (&[(FWD, "for=_hidden, for=1.2.3.4")], Some("1.2.3.4")),
+            (&[(FWD, "for=1.2.3.4, proto=https")], None),
+            (&[(FWD, "by=10.0.0.3")], None),
+            (&[("x-forwarded-for", "1.2.3.4")], Some("10.0.0.1")),
+        ] {
+            assert_eq!(
+                resolve(Forwarded, Some("10.0.0.1"), headers),
+                expected.map(ip),
+                "{headers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_x_real_ip_only_from_trusted_peers() {
+        use ForwardedHeader::XRealIp;
+        const REAL_IP: &str = "x-real-ip";
+
+        for (peer, headers, expected) in [
+            (
+                Some("10.0.0.1"),
+                &[(REAL_IP, "1.2.3.4")][..],
+                Some("1.2.3.4"),
+            ),
+            (
+                Some("10.0.0.1"),
+                &[(REAL_IP, "[2001:db8::1]:80")],
+                Some("2001:db8::1"),
+            ),
+            (Some("10.0.0.1"), &[], Some("10.0.0.1")),
+            (Some("10.0.0.1"), &[(REAL_IP, "garbage")], Some("10.0.0.1")),
+            (
+                Some("10.0.0.1"),
+                &[("x-forwarded-for", "1.2.3.4")],
+                Some("10.0.0.1"),
+            ),
+            (
+                Some("203.0.113.9"),
+                &[(REAL_IP, "1.2.3.4")],
+                Some("203.0.113.9"),
+            ),
+            (None, &[(REAL_IP, "1.2.3.4")], Some("1.2.3.4")),
+        ] {
+            assert_eq!(
+                resolve(XRealIp, peer, headers),
+                expected.map(ip),
+                "{peer:?} {headers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn non_utf8_header_breaks_the_chain() {
+        let proxies = ["10.0.0.0/8".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", HeaderValue::from_static("1.2.3.4"));
+        headers.append(
+            "x-forwarded-for",
+            HeaderValue::from_bytes(b"\xff10.0.0.2").unwrap(),
+        );
+        let proxies = TrustedProxies::new(&proxies, ForwardedHeader::XForwardedFor);
+        assert_eq!(proxies.resolve(Some(ip("10.0.0.1")), &headers), None);
+    }
+}
@@ -0,0 +1,465 @@
+//! 服务配置，优先级从低到高依次为：默认值、配置文件、`PT_*` 环境变量、命令行参数
+
+use std::{
+    env, fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+
+use crate::client_ip::{Cidr, ForwardedHeader};
+use crate::geo::GeoBackend;
+use crate::privacy::IpMode;
+
+/// 未指定配置文件时尝试读取的文件
+const DEFAULT_CONFIG_FILE: &str = "project-tracker.toml";
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// 配置文件路径，默认读取当前目录下的 project-tracker.toml
+    #[arg(short, long, env = "PT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// 监听地址，如 `127.0.0.1:3162`、`[::]:3162` 或 `unix:/run/project-tracker.sock`
+    #[arg(long)]
+    pub bind: Option<BindAddress>,
+
+    /// 数据库连接地址，如 `sqlite:project_tracker.db?mode=rwc` 或 `postgres://user@localhost/tracker`
+    #[arg(long)]
+    pub database_url: Option<String>,
+
+    /// 日志文件目录
+    #[arg(long)]
+    pub log_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 启动服务（默认）
+    Serve,
+    /// 执行数据库迁移
+    Migrate {
+        /// 只列出将要执行的迁移
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// 管理读取统计接口使用的 API key
+    ApiKey {
+        #[command(subcommand)]
+        command: ApiKeyCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ApiKeyCommand {
+    /// 创建 API key，key 只显示这一次
+    Create {
+        /// 便于识别用途的名称
+        #[arg(long)]
+        name: String,
+        /// 只能读取该项目的统计，未指定时可以读取所有项目
+        #[arg(long)]
+        project: Option<String>,
+    },
+    /// 列出所有 API key
+    List,
+    /// 吊销 API key
+    Revoke {
+        /// `list` 输出中的编号
+        id: i64,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub log: LogConfig,
+    pub geo: GeoConfig,
+    pub admin: AdminConfig,
+    pub stats: StatsConfig,
+    pub privacy: PrivacyConfig,
+    pub retention: RetentionConfig,
+    pub rate_limit: RateLimitConfig,
+    pub batch: BatchConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: BindAddress,
+    /// 受信任的代理地址段，只采信这些地址转发的客户端 IP 地址
+    pub trusted_proxies: Vec<Cidr>,
+    /// 受信任的代理写入客户端地址的请求头
+    pub forwarded_header: ForwardedHeader,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: BindAddress::Tcp(SocketAddr::from(([127, 0, 0, 1], 3162))),
+            trusted_proxies: vec![
+                Cidr::from_str("127.0.0.0/8").unwrap(),
+                Cidr::from_str("::1").unwrap(),
+            ],
+            forwarded_header: ForwardedHeader::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// `sqlite:` 开头使用 SQLite，`postgres://` 或 `postgresql://` 开头使用 PostgreSQL
+    pub url: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: "sqlite:project_tracker.db?mode=rwc".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// 生产环境日志文件目录
+    pub directory: PathBuf,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("log"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoConfig {
+    /// 查询后端，未设置时配置了 `mmdb_path` 则使用本地数据库，否则使用 ip-api.com
+    pub backend: Option<GeoBackend>,
+    /// 本地 `.mmdb` 文件路径
+    pub mmdb_path: Option<PathBuf>,
+    /// 查询结果缓存时间（秒）
+    pub cache_ttl: u64,
+    /// 最多缓存的 IP 数量
+    pub cache_capacity: usize,
+}
+
+impl Default for GeoConfig {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            mmdb_path: None,
+            cache_ttl: 60 * 60,
+            cache_capacity: 10_000,
+        }
+    }
+}
+
+impl GeoConfig {
+    pub fn backend(&self) -> GeoBackend {
+        match self.backend {
+            Some(backend) => backend,
+            None if self.mmdb_path.is_some() => GeoBackend::Mmdb,
+            None => GeoBackend::IpApi,
+        }
+    }
+
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// 管理接口令牌，未设置时不开放管理接口
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    /// 日期范围查询最多包含的天数
+    pub max_range_days: u32,
+    /// 为 `true` 时统计接口不需要 API key
+    pub public: bool,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            max_range_days: 366,
+            public: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    /// 保存访问者 IP 地址的方式
+    pub ip_mode: IpMode,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// 检查过期访问记录的间隔（秒），保留天数在各项目中设置
+    pub interval: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self { interval: 60 * 60 }
+    }
+}
+
+impl RetentionConfig {
+    pub fn interval(&self) -> Duration {
+        // 间隔为 0 时按 1 秒处理
+        Duration::from_secs(self.interval.max(1))
+    }
+}
+
+/// `/track` 的限流和去重，按项目分别计算
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// 每个 IP 地址每分钟最多记录的访问次数，为 0 时不限制
+    pub per_minute: u32,
+    /// 允许连续访问的次数
+    pub burst: u32,
+    /// 同一访客在该时间（秒）内只记录一次访问，为 0 时不去重
+    pub dedup_window: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_minute: 30,
+            burst: 10,
+            dedup_window: 0,
+        }
+    }
+}
+
+/// `/track/batch` 的限制
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchConfig {
+    /// 每次请求最多包含的访问数量
+    pub max_events: usize,
+    /// 客户端提供的访问时间最多早于服务器时间多少秒
+    pub max_age: u32,
+    /// 客户端提供的访问时间最多晚于服务器时间多少秒
+    pub max_skew: u32,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_events: 100,
+            max_age: 7 * 24 * 60 * 60,
+            max_skew: 5 * 60,
+        }
+    }
+}
+
+/// 服务监听地址
+#[derive(Debug, Clone)]
+pub enum BindAddress {
+    Tcp(SocketAddr),
+    /// Unix 域套接字，格式为 `unix:<path>`
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix socket path is empty".to_string());
+            }
+            return Ok(BindAddress::Unix(PathBuf::from(path)));
+        }
+
+        s.parse()
+            .map(BindAddress::Tcp)
+            .map_err(|e| format!("invalid bind address `{}`: {}", s, e))
+    }
+}
+
+impl fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindAddress::Tcp(addr) => write!(f, "http://{}", addr),
+            BindAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BindAddress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => {
+                write!(f, "failed to read config file {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "failed to parse config file {}: {}", path.display(), e)
+            }
+            ConfigError::Env(name, e) => write!(f, "invalid environment variable {}: {}", name, e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// 按优先级合并默认值、配置文件、环境变量和命令行参数
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.apply_cli(cli);
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(bind) = env_var("PT_BIND") {
+            self.server.bind = parse_env("PT_BIND", &bind)?;
+        }
+        if let Some(proxies) = env_var("PT_TRUSTED_PROXIES") {
+            self.server.trusted_proxies = proxies
+                .split(',')
+                .map(|cidr| parse_env("PT_TRUSTED_PROXIES", cidr.trim()))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(header) = env_var("PT_FORWARDED_HEADER") {
+            self.server.forwarded_header = parse_env("PT_FORWARDED_HEADER", &header)?;
+        }
+        if let Some(url) = env_var("PT_DATABASE_URL") {
+            self.database.url = url;
+        }
+        if let Some(directory) = env_var("PT_LOG_DIR") {
+            self.log.directory = PathBuf::from(directory);
+        }
+        if let Some(backend) = env_var("PT_GEO_BACKEND") {
+            self.geo.backend = Some(parse_env("PT_GEO_BACKEND", &backend)?);
+        }
+        if let Some(path) = env_var("PT_GEOIP_DATABASE") {
+            self.geo.mmdb_path = Some(PathBuf::from(path));
+        }
+        if let Some(ttl) = env_var("PT_GEO_CACHE_TTL") {
+            self.geo.cache_ttl = parse_env("PT_GEO_CACHE_TTL", &ttl)?;
+        }
+        if let Some(capacity) = env_var("PT_GEO_CACHE_CAPACITY") {
+            self.geo.cache_capacity = parse_env("PT_GEO_CACHE_CAPACITY", &capacity)?;
+        }
+        if let Some(token) = env_var("PT_ADMIN_TOKEN") {
+            self.admin.token = Some(token);
+        }
+        if let Some(mode) = env_var("PT_IP_MODE") {
+            self.privacy.ip_mode = parse_env("PT_IP_MODE", &mode)?;
+        }
+        if let Some(interval) = env_var("PT_RETENTION_INTERVAL") {
+            self.retention.interval = parse_env("PT_RETENTION_INTERVAL", &interval)?;
+        }
+        if let Some(per_minute) = env_var("PT_RATE_LIMIT_PER_MINUTE") {
+            self.rate_limit.per_minute = parse_env("PT_RATE_LIMIT_PER_MINUTE", &per_minute)?;
+        }
+        if let Some(burst) = env_var("PT_RATE_LIMIT_BURST") {
+            self.rate_limit.burst = parse_env("PT_RATE_LIMIT_BURST", &burst)?;
+        }
+        if let Some(window) = env_var("PT_DEDUP_WINDOW") {
+            self.rate_limit.dedup_window = parse_env("PT_DEDUP_WINDOW", &window)?;
+        }
+        if let Some(max_events) = env_var("PT_BATCH_MAX_EVENTS") {
+            self.batch.max_events = parse_env("PT_BATCH_MAX_EVENTS", &max_events)?;
+        }
+        if let Some(max_age) = env_var("PT_BATCH_MAX_AGE") {
+            self.batch.max_age = parse_env("PT_BATCH_MAX_AGE", &max_age)?;
+        }
+        if let Some(max_skew) = env_var("PT_BATCH_MAX_SKEW") {
+            self.batch.max_skew = parse_env("PT_BATCH_MAX_SKEW", &max_skew)?;
+        }
+        if let Some(days) = env_var("PT_MAX_RANGE_DAYS") {
+            self.stats.max_range_days = parse_env("PT_MAX_RANGE_DAYS", &days)?;
+        }
+        if let Some(public) = env_var("PT_STATS_PUBLIC") {
+            self.stats.public = parse_env("PT_STATS_PUBLIC", &public)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(bind) = &cli.bind {
+            self.server.bind = bind.clone();
+        }
+        if let Some(url) = &cli.database_url {
+            self.database.url = url.clone();
+        }
+        if let Some(directory) = &cli.log_dir {
+            self.log.directory = directory.clone();
+        }
+    }
+}
+
+/// 读取环境变量，空字符串视为未设置
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn parse_env<T>(name: &'static str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e: T::Err| ConfigError::Env(name, e.to_string()))
+}
@@ -1,181 +1,909 @@
-use sqlx::{SqlitePool, query, query_as};
+use sqlx::{AssertSqlSafe, FromRow, PgPool, SqlitePool, query, query_as, query_scalar};
+use time::{Date, OffsetDateTime};
 
-use crate::models::{CountryStats, Platform, Project, ProjectDetailedStats, ProjectStats, Visit};
+use crate::geo::GeoInfo;
+use crate::migrations;
+use crate::models::{
+    AllProjectsStats, ApiKey, CountryStats, DropReason, DroppedVisits, EventCount, NewEvent,
+    NewProject, NewVisit, ORPHANED_BUCKET, OrphanPolicy, OrphanedStats, Platform,
+    PlatformCountryStats, PlatformStats, Project, ProjectDetailedStats, ProjectRangeStats,
+    ProjectSlug, ProjectStats, ProjectUpdate, PropertyStats, PropertyValueCount, TimeRange,
+    VersionStats, Visit,
+};
+use crate::privacy::StoredIp;
 
-pub async fn init_database() -> Result<SqlitePool, sqlx::Error> {
-    let pool = SqlitePool::connect("sqlite:project_tracker.db?mode=rwc")
+/// 聚合查询的结果行，包含项目信息及其访问统计
+#[derive(FromRow)]
+struct ProjectStatsRow {
+    #[sqlx(flatten)]
+    project: Project,
+    total_visits: i64,
+    unique_visitors: i64,
+}
+
+/// 区分独立访客的依据，优先使用安装标识，客户端未上报时退回到 IP 地址
+///
+/// 加上前缀避免安装标识与 IP 地址的取值冲突
+const VISITOR_KEY: &str = "COALESCE('i:' || install_id, 'a:' || ip_address)";
+
+/// 统计查询的数据来源，合并原始访问记录和按天汇总的记录，汇总记录的时间为当天零点（UTC）
+///
+/// 原始记录的 `visit_count` 为 1，通过 `visitor_key` 计算独立访客；汇总记录只保存了每组的
+/// 独立访客数 `unique_estimate`，统计时直接相加，跨天或跨组重复的访客会被多次计算
+///
+/// `condition` 根据时间列生成两部分各自的筛选条件，原始记录为 `created_at`，汇总记录为 `day`。
+/// 条件在合并前生效，两部分才能分别使用 `(project_name, created_at)` 和 `(project_name, day)` 索引
+fn visit_source(condition: impl Fn(&str) -> String) -> String {
+    format!(
+        r#"(
+    SELECT
+        project_name, created_at, platform, country, app_version,
+        1 AS visit_count,
+        {VISITOR_KEY} AS visitor_key,
+        0 AS unique_estimate
+    FROM visits
+    WHERE {}
+    UNION ALL
+    SELECT
+        project_name, day, platform, country, app_version,
+        visit_count,
+        NULL,
+        unique_visitors
+    FROM daily_visits
+    WHERE {}
+)"#,
+        condition("created_at"),
+        condition("day")
+    )
+}
+
+/// 单个项目在时间范围内的记录，项目标识绑定为 `$1`，范围的开始和结束绑定为 `$2`、`$3`
+///
+/// 未指定范围时只绑定项目标识
+fn project_condition(dialect: Dialect, range: Option<TimeRange>) -> impl Fn(&str) -> String {
+    move |column| {
+        format!(
+            "project_name = $1 AND {}",
+            dialect.range_condition(column, 2, range)
+        )
+    }
+}
+
+/// 基于 [`visit_source`] 的访问次数
+///
+/// PostgreSQL 中 `SUM(BIGINT)` 的结果为 `NUMERIC`，需要转换回 `BIGINT`
+const TOTAL_VISITS: &str = "CAST(COALESCE(SUM(v.visit_count), 0) AS BIGINT)";
+
+/// 基于 [`visit_source`] 的独立访客数
+const UNIQUE_VISITORS: &str =
+    "CAST(COUNT(DISTINCT v.visitor_key) + COALESCE(SUM(v.unique_estimate), 0) AS BIGINT)";
+
+/// 数据库连接池，根据连接地址的协议选择 SQLite 或 PostgreSQL
+///
+/// 查询语句两种数据库通用，参数统一使用 `$1`、`$2` 形式的占位符，
+/// 无法通用的片段由 [`Dialect`] 生成
+#[derive(Debug, Clone)]
+pub enum DbPool {
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+/// 对两种连接池执行同一段查询代码，`$p` 在各分支中绑定为具体的连接池
+macro_rules! with_pool {
+    ($pool:expr, |$p:ident| $body:expr) => {
+        match $pool {
+            $crate::database::DbPool::Sqlite($p) => $body,
+            $crate::database::DbPool::Postgres($p) => $body,
+        }
+    };
+}
+
+pub(crate) use with_pool;
+
+impl DbPool {
+    pub fn dialect(&self) -> Dialect {
+        match self {
+            DbPool::Sqlite(_) => Dialect::Sqlite,
+            DbPool::Postgres(_) => Dialect::Postgres,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+impl Dialect {
+    /// 时间参数的占位符
+    ///
+    /// SQLite 以 `YYYY-MM-DD HH:MM:SS` 文本保存时间，绑定的时间需要转换为相同格式才能比较
+    fn timestamp_param(self, index: usize) -> String {
+        match self {
+            Dialect::Sqlite => format!("datetime(${})", index),
+            Dialect::Postgres => format!("${}", index),
+        }
+    }
+
+    /// `column` 位于时间范围内的条件，范围的开始和结束依次绑定为 `$index`、`$index + 1`
+    ///
+    /// 未指定范围时不限制，也不需要绑定参数
+    fn range_condition(self, column: &str, index: usize, range: Option<TimeRange>) -> String {
+        match range {
+            Some(_) => format!(
+                "{column} >= {} AND {column} < {}",
+                self.timestamp_param(index),
+                self.timestamp_param(index + 1)
+            ),
+            None => "1 = 1".to_string(),
+        }
+    }
+
+    /// `column` 所在日期的零点（UTC），与 `column` 的类型相同
+    fn day_start(self, column: &str) -> String {
+        match self {
+            Dialect::Sqlite => format!("datetime(date({column}))"),
+            Dialect::Postgres => {
+                format!("date_trunc('day', {column} AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'")
+            }
+        }
+    }
+}
+
+/// 连接数据库，`postgres://` 或 `postgresql://` 开头的地址使用 PostgreSQL，`sqlite:` 开头的使用 SQLite
+pub async fn connect(url: &str) -> Result<DbPool, sqlx::Error> {
+    let scheme = url.split_once(':').map_or("", |(scheme, _)| scheme);
+    let pool = match scheme {
+        "sqlite" => SqlitePool::connect(url).await.map(DbPool::Sqlite),
+        "postgres" | "postgresql" => PgPool::connect(url).await.map(DbPool::Postgres),
+        _ => Err(sqlx::Error::Configuration(
+            format!("unsupported database url scheme `{}`", scheme).into(),
+        )),
+    }
+    .map_err(|e| {
+        error!("数据库连接失败: {:?}", e);
+        e
+    })?;
+    info!("数据库连接成功: {:?}", pool.dialect());
+
+    Ok(pool)
+}
+
+/// 连接数据库并执行尚未执行的迁移
+pub async fn init_database(url: &str) -> Result<DbPool, Box<dyn std::error::Error>> {
+    let pool = connect(url).await?;
+
+    migrations::run(&pool, false).await.map_err(|e| {
+        error!("数据库迁移失败: {}", e);
+        e
+    })?;
+    info!("数据库结构版本: {}", migrations::latest_version());
+
+    Ok(pool)
+}
+
+/// 根据项目标识查询项目
+pub async fn get_project(
+    pool: &DbPool,
+    slug: &ProjectSlug,
+) -> Result<Option<Project>, sqlx::Error> {
+    let project = with_pool!(pool, |p| {
+        query_as::<_, Project>("SELECT * FROM projects WHERE slug = $1")
+            .bind(slug.as_str())
+            .fetch_optional(p)
+            .await
+    })
+    .map_err(|e| {
+        error!("项目查询失败: {:?}", e);
+        e
+    })?;
+
+    Ok(project)
+}
+
+/// 查询所有项目，包括已归档的项目
+pub async fn list_projects(pool: &DbPool) -> Result<Vec<Project>, sqlx::Error> {
+    let projects = with_pool!(pool, |p| {
+        query_as::<_, Project>("SELECT * FROM projects ORDER BY slug")
+            .fetch_all(p)
+            .await
+    })
+    .map_err(|e| {
+        error!("项目列表查询失败: {:?}", e);
+        e
+    })?;
+
+    Ok(projects)
+}
+
+/// 创建项目，项目标识已存在时返回 `None`
+pub async fn create_project(
+    pool: &DbPool,
+    slug: &ProjectSlug,
+    project: &NewProject,
+) -> Result<Option<Project>, sqlx::Error> {
+    let project = with_pool!(pool, |p| {
+        query_as::<_, Project>(
+            r#"
+            INSERT INTO projects (slug, repository, icon, description, retention_days)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (slug) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(slug.as_str())
+        .bind(&project.repository)
+        .bind(&project.icon)
+        .bind(&project.description)
+        .bind(
+            project
+                .retention_days
+                .filter(|&days| days > 0)
+                .map(i32::from),
+        )
+        .fetch_optional(p)
         .await
-        .map_err(|e| {
-            error!("数据库连接失败: {:?}", e);
-            e
-        })?;
-    info!("数据库连接成功");
+    })
+    .map_err(|e| {
+        error!("项目创建失败: {:?}", e);
+        e
+    })?;
 
-    // 创建表
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS visits (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            project_name TEXT NOT NULL,
-            platform TEXT NOT NULL,
-            ip_address TEXT NOT NULL,
-            country TEXT,
-            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    Ok(project)
+}
+
+/// 更新项目信息，项目不存在时返回 `None`
+pub async fn update_project(
+    pool: &DbPool,
+    slug: &ProjectSlug,
+    update: &ProjectUpdate,
+) -> Result<Option<Project>, sqlx::Error> {
+    let project = with_pool!(pool, |p| {
+        query_as::<_, Project>(
+            r#"
+            UPDATE projects SET
+                repository = COALESCE($1, repository),
+                icon = COALESCE($2, icon),
+                description = COALESCE($3, description),
+                archived_at = CASE
+                    WHEN $4 IS NULL THEN archived_at
+                    WHEN $4 THEN COALESCE(archived_at, CURRENT_TIMESTAMP)
+                    ELSE NULL
+                END,
+                retention_days = CASE
+                    WHEN $5 IS NULL THEN retention_days
+                    WHEN $5 = 0 THEN NULL
+                    ELSE $5
+                END
+            WHERE slug = $6
+            RETURNING *
+            "#,
         )
-        "#,
-    )
-    .execute(&pool)
-    .await
+        .bind(&update.repository)
+        .bind(&update.icon)
+        .bind(&update.description)
+        .bind(update.archived)
+        .bind(update.retention_days.map(i32::from))
+        .bind(slug.as_str())
+        .fetch_optional(p)
+        .await
+    })
     .map_err(|e| {
-        error!("数据库表创建失败: {:?}", e);
+        error!("项目更新失败: {:?}", e);
         e
     })?;
-    info!("数据库表创建成功");
 
-    // 创建索引
-    sqlx::query("CREATE INDEX IF NOT EXISTS idx_visits_project_name ON visits(project_name)")
-        .execute(&pool)
+    Ok(project)
+}
+
+/// 归档项目，保留历史访问记录，项目不存在时返回 `None`
+pub async fn archive_project(
+    pool: &DbPool,
+    slug: &ProjectSlug,
+) -> Result<Option<Project>, sqlx::Error> {
+    let project = with_pool!(pool, |p| {
+        query_as::<_, Project>(
+            r#"
+            UPDATE projects SET archived_at = COALESCE(archived_at, CURRENT_TIMESTAMP)
+            WHERE slug = $1
+            RETURNING *
+            "#,
+        )
+        .bind(slug.as_str())
+        .fetch_optional(p)
         .await
-        .map_err(|e| {
-            error!("数据库索引`idx_visits_project_name`创建失败: {:?}", e);
-            e
-        })?;
+    })
+    .map_err(|e| {
+        error!("项目归档失败: {:?}", e);
+        e
+    })?;
+
+    Ok(project)
+}
 
-    sqlx::query("CREATE INDEX IF NOT EXISTS idx_visits_platform ON visits(platform)")
-        .execute(&pool)
+/// 保存 API key 的哈希值，`project` 为空时可以读取所有项目
+pub async fn create_api_key(
+    pool: &DbPool,
+    name: &str,
+    key_hash: &str,
+    project: Option<&ProjectSlug>,
+) -> Result<ApiKey, sqlx::Error> {
+    let key = with_pool!(pool, |p| {
+        query_as::<_, ApiKey>(
+            r#"
+            INSERT INTO api_keys (name, key_hash, project_name)
+            VALUES ($1, $2, $3)
+            RETURNING id, name, project_name, created_at, revoked_at
+            "#,
+        )
+        .bind(name)
+        .bind(key_hash)
+        .bind(project.map(ProjectSlug::as_str))
+        .fetch_one(p)
         .await
-        .map_err(|e| {
-            error!("数据库索引`idx_visits_platform`创建失败: {:?}", e);
-            e
-        })?;
+    })
+    .map_err(|e| {
+        error!("API key 创建失败: {:?}", e);
+        e
+    })?;
+
+    Ok(key)
+}
 
-    sqlx::query("CREATE INDEX IF NOT EXISTS idx_visits_created_at ON visits(created_at)")
-        .execute(&pool)
+/// 查询所有 API key，包括已吊销的
+pub async fn list_api_keys(pool: &DbPool) -> Result<Vec<ApiKey>, sqlx::Error> {
+    let keys = with_pool!(pool, |p| {
+        query_as::<_, ApiKey>(
+            "SELECT id, name, project_name, created_at, revoked_at FROM api_keys ORDER BY id",
+        )
+        .fetch_all(p)
         .await
-        .map_err(|e| {
-            error!("数据库索引`idx_visits_created_at`创建失败: {:?}", e);
-            e
-        })?;
+    })
+    .map_err(|e| {
+        error!("API key 列表查询失败: {:?}", e);
+        e
+    })?;
 
-    info!("数据库索引创建成功");
+    Ok(keys)
+}
 
-    Ok(pool)
+/// 根据哈希值查询未吊销的 API key
+pub async fn find_api_key(pool: &DbPool, key_hash: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+    let key = with_pool!(pool, |p| {
+        query_as::<_, ApiKey>(
+            r#"
+            SELECT id, name, project_name, created_at, revoked_at FROM api_keys
+            WHERE key_hash = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(key_hash)
+        .fetch_optional(p)
+        .await
+    })
+    .map_err(|e| {
+        error!("API key 查询失败: {:?}", e);
+        e
+    })?;
+
+    Ok(key)
+}
+
+/// 吊销 API key，不存在时返回 `None`
+pub async fn revoke_api_key(pool: &DbPool, id: i64) -> Result<Option<ApiKey>, sqlx::Error> {
+    let key = with_pool!(pool, |p| {
+        query_as::<_, ApiKey>(
+            r#"
+            UPDATE api_keys SET revoked_at = COALESCE(revoked_at, CURRENT_TIMESTAMP)
+            WHERE id = $1
+            RETURNING id, name, project_name, created_at, revoked_at
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(p)
+        .await
+    })
+    .map_err(|e| {
+        error!("API key 吊销失败: {:?}", e);
+        e
+    })?;
+
+    Ok(key)
+}
+
+/// 在同一事务中插入来自同一客户端的访问记录，地理位置由后台任务补充
+pub async fn insert_visits(
+    pool: &DbPool,
+    ip: &StoredIp,
+    visits: &[(&Project, &NewVisit)],
+) -> Result<(), sqlx::Error> {
+    let sql = format!(
+        r#"
+        INSERT INTO visits (
+            project_name, platform, ip_address, pending_ip,
+            app_version, os_version, arch, install_id, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, COALESCE({}, CURRENT_TIMESTAMP))
+        "#,
+        pool.dialect().timestamp_param(9)
+    );
+    with_pool!(pool, |p| {
+        let mut tx = p.begin().await?;
+        for (project, visit) in visits {
+            query(AssertSqlSafe(sql.as_str()))
+                .bind(&project.slug)
+                .bind(visit.platform)
+                .bind(&ip.ip_address)
+                .bind(&ip.pending_ip)
+                .bind(&visit.app_version)
+                .bind(&visit.os_version)
+                .bind(&visit.arch)
+                .bind(&visit.install_id)
+                .bind(visit.created_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    })
+    .map_err(|e| {
+        error!("数据库插入失败: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+/// 在同一事务中插入自定义事件及其属性
+pub async fn insert_event(
+    pool: &DbPool,
+    project: &Project,
+    event: &NewEvent,
+) -> Result<(), sqlx::Error> {
+    with_pool!(pool, |p| {
+        let mut tx = p.begin().await?;
+        let id = query_scalar::<_, i64>(
+            "INSERT INTO events (project_name, name) VALUES ($1, $2) RETURNING id",
+        )
+        .bind(&project.slug)
+        .bind(&event.name)
+        .fetch_one(&mut *tx)
+        .await?;
+        for (key, value) in &event.properties {
+            query("INSERT INTO event_properties (event_id, key, value) VALUES ($1, $2, $3)")
+                .bind(id)
+                .bind(key)
+                .bind(value)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    })
+    .map_err(|e| {
+        error!("自定义事件插入失败: {:?}", e);
+        e
+    })?;
+
+    Ok(())
 }
 
-pub async fn insert_visit(
-    pool: &SqlitePool,
-    project_name: &Project,
-    platform: &Platform,
-    ip_address: &str,
-    country: Option<&str>,
+/// 累加项目当天（UTC）因 `reason` 未计入的访问次数
+pub async fn record_dropped_visits(
+    pool: &DbPool,
+    project_name: &str,
+    reason: DropReason,
+    count: i64,
 ) -> Result<(), sqlx::Error> {
-    query("INSERT INTO visits (project_name, platform, ip_address, country) VALUES (?, ?, ?, ?)")
+    let day = OffsetDateTime::now_utc().date().to_string();
+    with_pool!(pool, |p| {
+        query(
+            r#"
+            INSERT INTO dropped_visits (project_name, day, reason, hit_count)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (project_name, day, reason)
+            DO UPDATE SET hit_count = dropped_visits.hit_count + excluded.hit_count
+            "#,
+        )
         .bind(project_name)
-        .bind(platform)
-        .bind(ip_address)
-        .bind(country)
-        .execute(pool)
+        .bind(day)
+        .bind(reason)
+        .bind(count)
+        .execute(p)
         .await
-        .map_err(|e| {
-            error!("数据库插入失败: {:?}", e);
-            e
-        })?;
+        .map(|_| ())
+    })
+    .map_err(|e| {
+        error!("未计入的访问次数写入失败: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+/// 查询项目因限流或去重未计入的访问次数及因限流未记录的自定义事件数量
+pub async fn get_dropped_visits(
+    pool: &DbPool,
+    project: &Project,
+) -> Result<(DroppedVisits, u64), sqlx::Error> {
+    let rows = with_pool!(pool, |p| {
+        query_as::<_, (DropReason, i64)>(
+            r#"
+            SELECT reason, CAST(SUM(hit_count) AS BIGINT) FROM dropped_visits
+            WHERE project_name = $1
+            GROUP BY reason
+            "#,
+        )
+        .bind(&project.slug)
+        .fetch_all(p)
+        .await
+    })
+    .map_err(|e| {
+        error!("未计入的访问次数查询失败: {:?}", e);
+        e
+    })?;
+
+    let mut dropped = DroppedVisits::default();
+    let mut dropped_events = 0;
+    for (reason, count) in rows {
+        match reason {
+            DropReason::RateLimited => dropped.rate_limited = count as u64,
+            DropReason::Duplicate => dropped.duplicate = count as u64,
+            DropReason::EventRateLimited => dropped_events = count as u64,
+        }
+    }
+
+    Ok((dropped, dropped_events))
+}
+
+/// 查询尚未补充地理位置的访问记录，返回 `(id, ip_address)`，IP 地址已匿名化时返回暂存的原始地址
+pub async fn get_pending_geo_visits(
+    pool: &DbPool,
+    limit: i64,
+) -> Result<Vec<(i64, String)>, sqlx::Error> {
+    let visits = with_pool!(pool, |p| {
+        query_as::<_, (i64, String)>(
+            r#"
+            SELECT id, COALESCE(pending_ip, ip_address) FROM visits
+            WHERE country IS NULL
+            ORDER BY id
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(p)
+        .await
+    })
+    .map_err(|e| {
+        error!("待补充地理位置的访问记录查询失败: {:?}", e);
+        e
+    })?;
+
+    Ok(visits)
+}
+
+/// 写入访问记录的地理位置，同时清除暂存的原始 IP 地址
+pub async fn update_visit_geo(pool: &DbPool, id: i64, geo: &GeoInfo) -> Result<(), sqlx::Error> {
+    with_pool!(pool, |p| {
+        query(
+            r#"
+            UPDATE visits SET country = $1, region = $2, city = $3, pending_ip = NULL
+            WHERE id = $4
+            "#,
+        )
+        .bind(&geo.country)
+        .bind(&geo.region)
+        .bind(&geo.city)
+        .bind(id)
+        .execute(p)
+        .await
+        .map(|_| ())
+    })
+    .map_err(|e| {
+        error!("访问记录地理位置更新失败: {:?}", e);
+        e
+    })?;
 
     Ok(())
 }
 
+/// 将项目在 `cutoff` 之前的访问记录按天（UTC）汇总到 `daily_visits`，并删除这些原始记录，
+/// 返回删除的记录数
+///
+/// `cutoff` 应为某天的零点，保证每天的记录只汇总一次
+///
+/// 尚未补充地理位置的记录留到补充后的下一轮汇总，否则汇总后的国家将永远为空
+pub async fn roll_up_visits(
+    pool: &DbPool,
+    project: &Project,
+    cutoff: OffsetDateTime,
+) -> Result<u64, sqlx::Error> {
+    let day = pool.dialect().day_start("created_at");
+    let roll_up = |source: &str| {
+        format!(
+            r#"
+            INSERT INTO daily_visits (
+                project_name, day, platform, country, app_version, visit_count, unique_visitors
+            )
+            SELECT
+                project_name, {day}, platform, country, app_version,
+                COUNT(*), COUNT(DISTINCT {VISITOR_KEY})
+            FROM {source}
+            GROUP BY project_name, {day}, platform, country, app_version
+            "#
+        )
+    };
+
+    let removed = match pool {
+        // SQLite 的写事务是串行的，汇总和删除在同一事务中即可
+        DbPool::Sqlite(p) => {
+            let condition =
+                "project_name = $1 AND created_at < datetime($2) AND country IS NOT NULL";
+            let mut tx = p.begin().await?;
+            query(AssertSqlSafe(roll_up(&format!("visits WHERE {condition}"))))
+                .bind(&project.slug)
+                .bind(cutoff)
+                .execute(&mut *tx)
+                .await?;
+            let removed = query(AssertSqlSafe(format!(
+                "DELETE FROM visits WHERE {condition}"
+            )))
+            .bind(&project.slug)
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            Ok(removed)
+        }
+        // 在一条语句中删除并汇总，多个实例同时执行时不会重复汇总同一条记录
+        DbPool::Postgres(p) => {
+            let sql = format!(
+                r#"
+                WITH expired AS (
+                    DELETE FROM visits
+                    WHERE project_name = $1 AND created_at < $2 AND country IS NOT NULL
+                    RETURNING *
+                ),
+                rolled AS ({})
+                SELECT COUNT(*) FROM expired
+                "#,
+                roll_up("expired")
+            );
+            query_scalar::<_, i64>(AssertSqlSafe(sql))
+                .bind(&project.slug)
+                .bind(cutoff)
+                .fetch_one(p)
+                .await
+                .map(|removed| removed as u64)
+        }
+    }
+    .map_err(|e: sqlx::Error| {
+        error!("访问记录汇总失败: {:?}", e);
+        e
+    })?;
+
+    Ok(removed)
+}
+
+/// 读取 `day` 的盐，尚不存在时写入 `candidate`，同时删除之前的盐
+pub async fn get_or_create_ip_salt(
+    pool: &DbPool,
+    day: Date,
+    candidate: &str,
+) -> Result<String, sqlx::Error> {
+    let day = day.to_string();
+    let salt = with_pool!(pool, |p| {
+        query("INSERT INTO ip_salts (day, salt) VALUES ($1, $2) ON CONFLICT (day) DO NOTHING")
+            .bind(&day)
+            .bind(candidate)
+            .execute(p)
+            .await?;
+        query("DELETE FROM ip_salts WHERE day < $1")
+            .bind(&day)
+            .execute(p)
+            .await?;
+        query_scalar::<_, String>("SELECT salt FROM ip_salts WHERE day = $1")
+            .bind(&day)
+            .fetch_one(p)
+            .await
+    })
+    .map_err(|e| {
+        error!("IP 地址哈希盐读取失败: {:?}", e);
+        e
+    })?;
+
+    Ok(salt)
+}
+
 pub async fn get_project_stats(
-    pool: &SqlitePool,
-    project_name: &Project,
+    pool: &DbPool,
+    project: &Project,
 ) -> Result<ProjectStats, sqlx::Error> {
-    let stats = query_as::<_, (u64, u64)>(
+    query_project_stats(pool, project, None).await.map_err(|e| {
+        error!("数据库查询失败: {:?}", e);
+        e
+    })
+}
+
+pub async fn get_all_projects_stats(
+    pool: &DbPool,
+    orphans: OrphanPolicy,
+) -> Result<AllProjectsStats, sqlx::Error> {
+    query_all_projects_stats(pool, None, orphans).await
+}
+
+/// 按国家统计项目的访问，`range` 为 `None` 时统计全部时间
+pub async fn get_country_stats(
+    pool: &DbPool,
+    project: &Project,
+    range: Option<TimeRange>,
+) -> Result<Vec<CountryStats>, sqlx::Error> {
+    let sql = format!(
         r#"
         SELECT
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE project_name = ?
+            country,
+            {TOTAL_VISITS} as visit_count
+        FROM {} v
+        GROUP BY country
+        ORDER BY visit_count DESC
         "#,
-    )
-    .bind(project_name)
-    .fetch_one(pool)
-    .await
+        visit_source(project_condition(pool.dialect(), range))
+    );
+    let stats = with_pool!(pool, |p| {
+        let mut stats_query = query_as::<_, CountryStats>(AssertSqlSafe(sql)).bind(&project.slug);
+        if let Some((start, end)) = range {
+            stats_query = stats_query.bind(start).bind(end);
+        }
+        stats_query.fetch_all(p).await
+    })
     .map_err(|e| {
         error!("数据库查询失败: {:?}", e);
         e
     })?;
 
-    Ok(ProjectStats::new(project_name.clone(), stats.0, stats.1))
+    Ok(stats)
 }
 
-pub async fn get_all_projects_stats(pool: &SqlitePool) -> Result<Vec<ProjectStats>, sqlx::Error> {
-    let stats = query_as::<_, (String, u64, u64)>(
+/// 按平台统计项目的访问，`range` 为 `None` 时统计全部时间
+pub async fn get_platform_stats(
+    pool: &DbPool,
+    project: &Project,
+    range: Option<TimeRange>,
+) -> Result<Vec<PlatformStats>, sqlx::Error> {
+    let sql = format!(
         r#"
         SELECT
-            project_name,
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        GROUP BY project_name
-        ORDER BY total_visits DESC
+            platform,
+            {TOTAL_VISITS} as visit_count
+        FROM {} v
+        GROUP BY platform
+        ORDER BY visit_count DESC
         "#,
-    )
-    .fetch_all(pool)
-    .await
+        visit_source(project_condition(pool.dialect(), range))
+    );
+    let stats = with_pool!(pool, |p| {
+        let mut stats_query = query_as::<_, PlatformStats>(AssertSqlSafe(sql)).bind(&project.slug);
+        if let Some((start, end)) = range {
+            stats_query = stats_query.bind(start).bind(end);
+        }
+        stats_query.fetch_all(p).await
+    })
     .map_err(|e| {
-        error!("数据库查询失败: {:?}", e);
+        error!("按平台查询数据库失败: {:?}", e);
         e
     })?;
 
-    Ok(stats
-        .into_iter()
-        .map(|s| ProjectStats::new_unchecked(s.0, s.1, s.2))
-        .collect())
+    Ok(stats)
 }
 
-pub async fn get_country_stats(
-    pool: &SqlitePool,
-    project_name: &Project,
-) -> Result<Vec<CountryStats>, sqlx::Error> {
-    let stats = query_as::<_, CountryStats>(
+/// 按应用版本统计项目的访问，按访问次数降序排列
+pub async fn get_version_stats(
+    pool: &DbPool,
+    project: &Project,
+    range: Option<TimeRange>,
+) -> Result<Vec<VersionStats>, sqlx::Error> {
+    let sql = format!(
+        r#"
+        SELECT
+            app_version,
+            {TOTAL_VISITS} as visit_count,
+            {UNIQUE_VISITORS} as unique_visitors
+        FROM {} v
+        GROUP BY app_version
+        ORDER BY visit_count DESC
+        "#,
+        visit_source(project_condition(pool.dialect(), range))
+    );
+    let stats = with_pool!(pool, |p| {
+        let mut stats_query = query_as::<_, VersionStats>(AssertSqlSafe(sql)).bind(&project.slug);
+        if let Some((start, end)) = range {
+            stats_query = stats_query.bind(start).bind(end);
+        }
+        stats_query.fetch_all(p).await
+    })
+    .map_err(|e| {
+        error!("按应用版本查询数据库失败: {:?}", e);
+        e
+    })?;
+
+    Ok(stats)
+}
+
+/// 按平台和国家交叉统计项目的访问，平台和各平台下的国家均按访问次数降序排列
+pub async fn get_platform_country_stats(
+    pool: &DbPool,
+    project: &Project,
+    range: Option<TimeRange>,
+) -> Result<Vec<PlatformCountryStats>, sqlx::Error> {
+    let sql = format!(
         r#"
         SELECT
+            platform,
             country,
-            COUNT(*) as visit_count
-        FROM visits
-        WHERE project_name = ?
-        GROUP BY country
+            {TOTAL_VISITS} as visit_count
+        FROM {} v
+        GROUP BY platform, country
         ORDER BY visit_count DESC
         "#,
-    )
-    .bind(project_name)
-    .fetch_all(pool)
-    .await
+        visit_source(project_condition(pool.dialect(), range))
+    );
+    let rows = with_pool!(pool, |p| {
+        let mut stats_query =
+            query_as::<_, (Platform, Option<String>, i64)>(AssertSqlSafe(sql)).bind(&project.slug);
+        if let Some((start, end)) = range {
+            stats_query = stats_query.bind(start).bind(end);
+        }
+        stats_query.fetch_all(p).await
+    })
     .map_err(|e| {
-        error!("数据库查询失败: {:?}", e);
+        error!("按平台和国家查询数据库失败: {:?}", e);
         e
     })?;
 
+    let mut stats: Vec<PlatformCountryStats> = Vec::new();
+    for (platform, country, visit_count) in rows {
+        let country = CountryStats {
+            country,
+            visit_count,
+        };
+        match stats.iter_mut().find(|s| s.platform == platform) {
+            Some(s) => {
+                s.visit_count += visit_count;
+                s.countries.push(country);
+            }
+            None => stats.push(PlatformCountryStats {
+                platform,
+                visit_count,
+                countries: vec![country],
+            }),
+        }
+    }
+    stats.sort_by_key(|s| std::cmp::Reverse(s.visit_count));
+
     Ok(stats)
 }
 
 pub async fn get_recent_visits(
-    pool: &SqlitePool,
-    project_name: &Project,
+    pool: &DbPool,
+    project: &Project,
     limit: i32,
 ) -> Result<Vec<Visit>, sqlx::Error> {
-    let visits: Vec<Visit> = query_as::<_, Visit>(
-        r#"
-        SELECT * FROM visits
-        WHERE project_name = ?
-        ORDER BY created_at DESC
-        LIMIT ?
-        "#,
-    )
-    .bind(project_name)
-    .bind(limit)
-    .fetch_all(pool)
-    .await
+    let visits = with_pool!(pool, |p| {
+        query_as::<_, Visit>(
+            r#"
+            SELECT
+                id, project_name, platform, country, region, city, created_at,
+                app_version, os_version, arch
+            FROM visits
+            WHERE project_name = $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(&project.slug)
+        .bind(limit)
+        .fetch_all(p)
+        .await
+    })
     .map_err(|e| {
         error!("数据库查询失败: {:?}", e);
         e
@@ -185,233 +913,427 @@ pub async fn get_recent_visits(
 }
 
 pub async fn get_project_detailed_stats(
-    pool: &SqlitePool,
-    project_name: &Project,
+    pool: &DbPool,
+    project: &Project,
 ) -> Result<ProjectDetailedStats, sqlx::Error> {
-    let basic_stats = get_project_stats(pool, project_name).await?;
-    let country_stats = get_country_stats(pool, project_name).await?;
-    let recent_visits = get_recent_visits(pool, project_name, 10).await?;
-
-    let project_name = basic_stats.project_name;
-    let repository = project_name.repository().to_owned();
-    let icon = project_name.icon().to_owned();
-    let description = project_name.description().to_owned();
+    let basic_stats = get_project_stats(pool, project).await?;
+    let country_stats = get_country_stats(pool, project, None).await?;
+    let platform_stats = get_platform_stats(pool, project, None).await?;
+    let recent_visits = get_recent_visits(pool, project, 10).await?;
+    let (dropped_visits, _) = get_dropped_visits(pool, project).await?;
 
     Ok(ProjectDetailedStats {
-        project_name,
-        repository,
-        icon,
-        description,
+        project_name: basic_stats.project_name,
+        repository: basic_stats.repository,
+        icon: basic_stats.icon,
+        description: basic_stats.description,
         total_visits: basic_stats.total_visits,
         unique_visitors: basic_stats.unique_visitors,
         country_stats,
+        platform_stats,
         recent_visits,
+        dropped_visits,
+    })
+}
+
+/// 查询项目在时间范围内的统计及按国家、平台的分布
+pub async fn get_project_stats_in_range(
+    pool: &DbPool,
+    project: &Project,
+    range: TimeRange,
+) -> Result<ProjectRangeStats, sqlx::Error> {
+    let basic_stats = query_project_stats(pool, project, Some(range))
+        .await
+        .map_err(|e| {
+            error!("按时间范围查询数据库失败: {:?}", e);
+            e
+        })?;
+    let country_stats = get_country_stats(pool, project, Some(range)).await?;
+    let platform_stats = get_platform_stats(pool, project, Some(range)).await?;
+
+    Ok(ProjectRangeStats {
+        project_name: basic_stats.project_name,
+        repository: basic_stats.repository,
+        icon: basic_stats.icon,
+        description: basic_stats.description,
+        total_visits: basic_stats.total_visits,
+        unique_visitors: basic_stats.unique_visitors,
+        country_stats,
+        platform_stats,
     })
 }
 
-/// 根据特定日期查询项目统计（格式：YYYY-MM-DD）
-pub async fn get_project_stats_by_date(
-    pool: &SqlitePool,
+/// 统计单个项目，`range` 为 `None` 时统计全部时间
+async fn query_project_stats(
+    pool: &DbPool,
     project: &Project,
-    date: &str,
+    range: Option<TimeRange>,
 ) -> Result<ProjectStats, sqlx::Error> {
-    let stats = query_as::<_, (u64, u64)>(
+    let sql = format!(
         r#"
         SELECT
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE project_name = ?
-        AND DATE(created_at) = ?
+            {TOTAL_VISITS} as total_visits,
+            {UNIQUE_VISITORS} as unique_visitors
+        FROM {} v
         "#,
-    )
-    .bind(project)
-    .bind(date)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| {
-        error!("按日期查询数据库失败: {:?}", e);
-        e
+        visit_source(project_condition(pool.dialect(), range))
+    );
+    let (total_visits, unique_visitors) = with_pool!(pool, |p| {
+        let mut stats_query = query_as::<_, (i64, i64)>(AssertSqlSafe(sql)).bind(&project.slug);
+        if let Some((start, end)) = range {
+            stats_query = stats_query.bind(start).bind(end);
+        }
+        stats_query.fetch_one(p).await
     })?;
 
-    Ok(ProjectStats::new(project.clone(), stats.0, stats.1))
+    Ok(ProjectStats::new(project, total_visits, unique_visitors))
 }
 
-/// 根据特定月份查询项目统计（格式：YYYY-MM）
-pub async fn get_project_stats_by_month(
-    pool: &SqlitePool,
+/// 按时间段统计项目，`buckets` 为左闭右开的时间区间，按顺序返回每个区间的
+/// `(total_visits, unique_visitors)`，没有访问记录的区间计数为 0
+///
+/// 原始记录和汇总记录分别统计后相加，与 [`visit_source`] 的计算方式相同；
+/// 分开查询时每个区间都可以通过索引查找，不需要对合并后的记录逐个区间扫描
+pub async fn get_project_series(
+    pool: &DbPool,
     project: &Project,
-    year_month: &str,
-) -> Result<ProjectStats, sqlx::Error> {
-    let stats = query_as::<_, (u64, u64)>(
+    buckets: &[(OffsetDateTime, OffsetDateTime)],
+) -> Result<Vec<(i64, i64)>, sqlx::Error> {
+    let dialect = pool.dialect();
+    let values = (0..buckets.len())
+        .map(|i| {
+            format!(
+                "({}, {}, {})",
+                i,
+                dialect.timestamp_param(i * 2 + 2),
+                dialect.timestamp_param(i * 2 + 3)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let visits_sql = format!(
         r#"
+        WITH buckets (idx, bucket_start, bucket_end) AS (VALUES {values})
         SELECT
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE project_name = ?
-        AND strftime('%Y-%m', created_at) = ?
-        "#,
-    )
-    .bind(project)
-    .bind(year_month)
-    .fetch_one(pool)
-    .await
+            COUNT(v.id) as total_visits,
+            COUNT(DISTINCT {VISITOR_KEY}) as unique_visitors
+        FROM buckets b
+        LEFT JOIN visits v
+            ON v.project_name = $1
+            AND v.created_at >= b.bucket_start
+            AND v.created_at < b.bucket_end
+        GROUP BY b.idx
+        ORDER BY b.idx
+        "#
+    );
+    let daily_sql = format!(
+        r#"
+        WITH buckets (idx, bucket_start, bucket_end) AS (VALUES {values})
+        SELECT
+            CAST(COALESCE(SUM(d.visit_count), 0) AS BIGINT) as total_visits,
+            CAST(COALESCE(SUM(d.unique_visitors), 0) AS BIGINT) as unique_visitors
+        FROM buckets b
+        LEFT JOIN daily_visits d
+            ON d.project_name = $1
+            AND d.day >= b.bucket_start
+            AND d.day < b.bucket_end
+        GROUP BY b.idx
+        ORDER BY b.idx
+        "#
+    );
+
+    let visits = query_series(pool, visits_sql, project, buckets).await?;
+    let daily = query_series(pool, daily_sql, project, buckets).await?;
+
+    Ok(visits
+        .into_iter()
+        .zip(daily)
+        .map(|((total, unique), (daily_total, daily_unique))| {
+            (total + daily_total, unique + daily_unique)
+        })
+        .collect())
+}
+
+/// 执行 [`get_project_series`] 中的一条查询，项目标识绑定为 `$1`，之后依次绑定各区间的开始和结束
+async fn query_series(
+    pool: &DbPool,
+    sql: String,
+    project: &Project,
+    buckets: &[(OffsetDateTime, OffsetDateTime)],
+) -> Result<Vec<(i64, i64)>, sqlx::Error> {
+    with_pool!(pool, |p| {
+        let mut series_query = query_as::<_, (i64, i64)>(AssertSqlSafe(sql)).bind(&project.slug);
+        for (start, end) in buckets {
+            series_query = series_query.bind(*start).bind(*end);
+        }
+        series_query.fetch_all(p).await
+    })
     .map_err(|e| {
-        error!("按月份查询数据库失败: {:?}", e);
+        error!("按时间段查询数据库失败: {:?}", e);
         e
-    })?;
+    })
+}
 
-    Ok(ProjectStats::new(project.clone(), stats.0, stats.1))
+/// 获取所有项目在时间范围内的统计
+pub async fn get_all_projects_stats_in_range(
+    pool: &DbPool,
+    range: TimeRange,
+    orphans: OrphanPolicy,
+) -> Result<AllProjectsStats, sqlx::Error> {
+    query_all_projects_stats(pool, Some(range), orphans).await
 }
 
-/// 根据特定年份查询项目统计（格式：YYYY）
-pub async fn get_project_stats_by_year(
-    pool: &SqlitePool,
-    project: &Project,
-    year: &str,
-) -> Result<ProjectStats, sqlx::Error> {
-    let stats = query_as::<_, (u64, u64)>(
+/// 统计所有项目，`range` 为 `None` 时统计全部时间，范围的开始和结束绑定为 `$1`、`$2`
+///
+/// 项目名称未在 `projects` 表中登记的访问记录按 `orphans` 处理
+async fn query_all_projects_stats(
+    pool: &DbPool,
+    range: Option<TimeRange>,
+    orphans: OrphanPolicy,
+) -> Result<AllProjectsStats, sqlx::Error> {
+    let dialect = pool.dialect();
+    let source = visit_source(|column| dialect.range_condition(column, 1, range));
+    let sql = format!(
         r#"
         SELECT
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE project_name = ?
-        AND strftime('%Y', created_at) = ?
-        "#,
-    )
-    .bind(project)
-    .bind(year)
-    .fetch_one(pool)
-    .await
+            p.*,
+            {TOTAL_VISITS} as total_visits,
+            {UNIQUE_VISITORS} as unique_visitors
+        FROM {source} v
+        JOIN projects p ON p.slug = v.project_name
+        GROUP BY p.slug
+        ORDER BY total_visits DESC
+        "#
+    );
+    let projects = with_pool!(pool, |p| {
+        let mut projects_query = query_as::<_, ProjectStatsRow>(AssertSqlSafe(sql));
+        if let Some((start, end)) = range {
+            projects_query = projects_query.bind(start).bind(end);
+        }
+        projects_query.fetch_all(p).await
+    })
     .map_err(|e| {
-        error!("按年份查询数据库失败: {:?}", e);
+        error!("查询所有项目统计失败: {:?}", e);
         e
-    })?;
+    })?
+    .into_iter()
+    .map(|row| ProjectStats::new(&row.project, row.total_visits, row.unique_visitors))
+    .collect();
+
+    let orphaned = match orphans {
+        OrphanPolicy::Skip => None,
+        OrphanPolicy::Report | OrphanPolicy::Bucket => {
+            // 汇总为一组时不按项目名称分组，保证独立访客数不会重复计算
+            let (name, group_by) = match orphans {
+                OrphanPolicy::Bucket => (format!("'{}'", ORPHANED_BUCKET), ""),
+                _ => ("v.project_name".to_string(), "GROUP BY v.project_name"),
+            };
+            let sql = format!(
+                r#"
+                SELECT
+                    {name} as project_name,
+                    {TOTAL_VISITS} as total_visits,
+                    {UNIQUE_VISITORS} as unique_visitors
+                FROM {source} v
+                WHERE NOT EXISTS (SELECT 1 FROM projects p WHERE p.slug = v.project_name)
+                {group_by}
+                HAVING COUNT(*) > 0
+                ORDER BY total_visits DESC
+                "#
+            );
+            let orphaned = with_pool!(pool, |p| {
+                let mut orphans_query = query_as::<_, OrphanedStats>(AssertSqlSafe(sql));
+                if let Some((start, end)) = range {
+                    orphans_query = orphans_query.bind(start).bind(end);
+                }
+                orphans_query.fetch_all(p).await
+            })
+            .map_err(|e| {
+                error!("查询未登记项目的访问统计失败: {:?}", e);
+                e
+            })?;
+
+            if !orphaned.is_empty() {
+                warn!(
+                    "存在未登记项目的访问记录: {:?}",
+                    orphaned.iter().map(|o| &o.project_name).collect::<Vec<_>>()
+                );
+            }
 
-    Ok(ProjectStats::new(project.clone(), stats.0, stats.1))
+            Some(orphaned)
+        }
+    };
+
+    Ok(AllProjectsStats { projects, orphaned })
 }
 
-/// 获取所有项目在特定日期的统计（格式：YYYY-MM-DD）
-pub async fn get_all_projects_stats_by_date(
-    pool: &SqlitePool,
-    date: &str,
-) -> Result<Vec<ProjectStats>, sqlx::Error> {
-    let stats = query_as::<_, (String, u64, u64)>(
+/// 按名称统计项目的自定义事件，按次数降序排列，`range` 为 `None` 时统计全部时间
+pub async fn get_event_counts(
+    pool: &DbPool,
+    project: &Project,
+    range: Option<TimeRange>,
+) -> Result<Vec<EventCount>, sqlx::Error> {
+    let sql = format!(
         r#"
-        SELECT
-            project_name,
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE DATE(created_at) = ?
-        GROUP BY project_name
-        ORDER BY total_visits DESC
+        SELECT name, COUNT(*) as count
+        FROM events
+        WHERE project_name = $1
+        AND {}
+        GROUP BY name
+        ORDER BY count DESC, name
         "#,
-    )
-    .bind(date)
-    .fetch_all(pool)
-    .await
+        pool.dialect().range_condition("created_at", 2, range)
+    );
+    let events = with_pool!(pool, |p| {
+        let mut events_query = query_as::<_, EventCount>(AssertSqlSafe(sql)).bind(&project.slug);
+        if let Some((start, end)) = range {
+            events_query = events_query.bind(start).bind(end);
+        }
+        events_query.fetch_all(p).await
+    })
     .map_err(|e| {
-        error!("按日期查询所有项目数据库失败: {:?}", e);
+        error!("自定义事件查询失败: {:?}", e);
         e
     })?;
 
-    Ok(stats
-        .into_iter()
-        .map(|s| ProjectStats::new_unchecked(s.0, s.1, s.2))
-        .collect())
+    Ok(events)
 }
 
-/// 获取所有项目在特定月份的统计（格式：YYYY-MM）
-pub async fn get_all_projects_stats_by_month(
-    pool: &SqlitePool,
-    year_month: &str,
-) -> Result<Vec<ProjectStats>, sqlx::Error> {
-    let stats = query_as::<_, (String, u64, u64)>(
+/// 统计项目中名为 `name` 的自定义事件的发生次数
+pub async fn get_event_count(
+    pool: &DbPool,
+    project: &Project,
+    name: &str,
+    range: Option<TimeRange>,
+) -> Result<i64, sqlx::Error> {
+    let sql = format!(
         r#"
-        SELECT
-            project_name,
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE strftime('%Y-%m', created_at) = ?
-        GROUP BY project_name
-        ORDER BY total_visits DESC
+        SELECT COUNT(*) FROM events
+        WHERE project_name = $1 AND name = $2
+        AND {}
         "#,
-    )
-    .bind(year_month)
-    .fetch_all(pool)
-    .await
+        pool.dialect().range_condition("created_at", 3, range)
+    );
+    let count = with_pool!(pool, |p| {
+        let mut count_query = query_scalar::<_, i64>(AssertSqlSafe(sql))
+            .bind(&project.slug)
+            .bind(name);
+        if let Some((start, end)) = range {
+            count_query = count_query.bind(start).bind(end);
+        }
+        count_query.fetch_one(p).await
+    })
     .map_err(|e| {
-        error!("按月份查询所有项目数据库失败: {:?}", e);
+        error!("自定义事件查询失败: {:?}", e);
         e
     })?;
 
-    Ok(stats
-        .into_iter()
-        .map(|s| ProjectStats::new_unchecked(s.0, s.1, s.2))
-        .collect())
+    Ok(count)
 }
 
-/// 获取所有项目在特定年份的统计（格式：YYYY）
-pub async fn get_all_projects_stats_by_year(
-    pool: &SqlitePool,
-    year: &str,
-) -> Result<Vec<ProjectStats>, sqlx::Error> {
-    let stats = query_as::<_, (String, u64, u64)>(
+/// 统计名为 `name` 的自定义事件每个属性出现次数最多的 `limit` 个取值，属性按名称排列
+pub async fn get_event_property_stats(
+    pool: &DbPool,
+    project: &Project,
+    name: &str,
+    range: Option<TimeRange>,
+    limit: i64,
+) -> Result<Vec<PropertyStats>, sqlx::Error> {
+    let sql = format!(
         r#"
-        SELECT
-            project_name,
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE strftime('%Y', created_at) = ?
-        GROUP BY project_name
-        ORDER BY total_visits DESC
+        SELECT key, value, count FROM (
+            SELECT
+                ep.key, ep.value, COUNT(*) as count,
+                ROW_NUMBER() OVER (PARTITION BY ep.key ORDER BY COUNT(*) DESC, ep.value) as rank
+            FROM events e
+            JOIN event_properties ep ON ep.event_id = e.id
+            WHERE e.project_name = $1 AND e.name = $2
+            AND {}
+            GROUP BY ep.key, ep.value
+        ) ranked
+        WHERE rank <= $3
+        ORDER BY key, rank
         "#,
-    )
-    .bind(year)
-    .fetch_all(pool)
-    .await
+        pool.dialect().range_condition("e.created_at", 4, range)
+    );
+    let rows = with_pool!(pool, |p| {
+        let mut stats_query = query_as::<_, (String, String, i64)>(AssertSqlSafe(sql))
+            .bind(&project.slug)
+            .bind(name)
+            .bind(limit);
+        if let Some((start, end)) = range {
+            stats_query = stats_query.bind(start).bind(end);
+        }
+        stats_query.fetch_all(p).await
+    })
     .map_err(|e| {
-        error!("按年份查询所有项目数据库失败: {:?}", e);
+        error!("自定义事件属性查询失败: {:?}", e);
         e
     })?;
 
-    Ok(stats
-        .into_iter()
-        .map(|s| ProjectStats::new_unchecked(s.0, s.1, s.2))
-        .collect())
+    let mut stats: Vec<PropertyStats> = Vec::new();
+    for (key, value, count) in rows {
+        let value = PropertyValueCount { value, count };
+        match stats.last_mut() {
+            Some(s) if s.key == key => s.values.push(value),
+            _ => stats.push(PropertyStats {
+                key,
+                values: vec![value],
+            }),
+        }
+    }
+
+    Ok(stats)
 }
 
-/// 根据日期范围查询项目统计（格式：YYYY-MM-DD）
-pub async fn get_project_stats_by_date_range(
-    pool: &SqlitePool,
+/// 按时间段统计名为 `name` 的自定义事件，`buckets` 为左闭右开的时间区间，
+/// 按顺序返回每个区间的发生次数，没有发生的区间计数为 0
+pub async fn get_event_series(
+    pool: &DbPool,
     project: &Project,
-    start_date: &str,
-    end_date: &str,
-) -> Result<ProjectStats, sqlx::Error> {
-    let stats = query_as::<_, (u64, u64)>(
+    name: &str,
+    buckets: &[(OffsetDateTime, OffsetDateTime)],
+) -> Result<Vec<i64>, sqlx::Error> {
+    let dialect = pool.dialect();
+    let values = (0..buckets.len())
+        .map(|i| {
+            format!(
+                "({}, {}, {})",
+                i,
+                dialect.timestamp_param(i * 2 + 3),
+                dialect.timestamp_param(i * 2 + 4)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!(
         r#"
-        SELECT
-            COUNT(*) as total_visits,
-            COUNT(DISTINCT ip_address) as unique_visitors
-        FROM visits
-        WHERE project_name = ?
-        AND DATE(created_at) BETWEEN ? AND ?
-        "#,
-    )
-    .bind(project)
-    .bind(start_date)
-    .bind(end_date)
-    .fetch_one(pool)
-    .await
+        WITH buckets (idx, bucket_start, bucket_end) AS (VALUES {values})
+        SELECT COUNT(e.id)
+        FROM buckets b
+        LEFT JOIN events e
+            ON e.project_name = $1
+            AND e.name = $2
+            AND e.created_at >= b.bucket_start
+            AND e.created_at < b.bucket_end
+        GROUP BY b.idx
+        ORDER BY b.idx
+        "#
+    );
+
+    let series = with_pool!(pool, |p| {
+        let mut series_query = query_scalar::<_, i64>(AssertSqlSafe(sql))
+            .bind(&project.slug)
+            .bind(name);
+        for (start, end) in buckets {
+            series_query = series_query.bind(*start).bind(*end);
+        }
+        series_query.fetch_all(p).await
+    })
     .map_err(|e| {
-        error!("按日期范围查询数据库失败: {:?}", e);
+        error!("自定义事件按时间段查询失败: {:?}", e);
         e
     })?;
 
-    Ok(ProjectStats::new(project.clone(), stats.0, stats.1))
+    Ok(series)
 }
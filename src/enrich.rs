@@ -0,0 +1,85 @@
+//! 后台补充访问记录的地理位置，避免地理位置查询拖慢 `/track` 的响应
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::Notify;
+
+use crate::{
+    database::{self, DbPool},
+    geo::{self, GeoResolver},
+};
+
+/// 每批处理的访问记录数量
+const BATCH_SIZE: i64 = 100;
+
+/// 没有新访问时检查待处理记录的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 查询服务不可用时的初始重试间隔
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// 查询服务不可用时的最大重试间隔
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// 后台任务的句柄，插入访问记录后通过它唤醒任务
+#[derive(Clone)]
+pub struct GeoEnricher {
+    notify: Arc<Notify>,
+}
+
+impl GeoEnricher {
+    pub fn spawn(pool: DbPool, resolver: Arc<dyn GeoResolver>) -> Self {
+        let notify = Arc::new(Notify::new());
+        tokio::spawn(run(pool, resolver, notify.clone()));
+
+        Self { notify }
+    }
+
+    /// 有新的待处理记录
+    pub fn wake(&self) {
+        self.notify.notify_one();
+    }
+}
+
+async fn run(pool: DbPool, resolver: Arc<dyn GeoResolver>, notify: Arc<Notify>) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let pending = match database::get_pending_geo_visits(&pool, BATCH_SIZE).await {
+            Ok(pending) => pending,
+            Err(_) => {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+
+        if pending.is_empty() {
+            let _ = tokio::time::timeout(POLL_INTERVAL, notify.notified()).await;
+            continue;
+        }
+
+        match enrich_batch(&pool, resolver.as_ref(), pending).await {
+            Ok(()) => backoff = INITIAL_BACKOFF,
+            Err(e) => {
+                warn!("地理位置补充失败，{} 秒后重试: {}", backoff.as_secs(), e);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// 处理一批记录，查询服务不可用时中止并返回错误
+async fn enrich_batch(
+    pool: &DbPool,
+    resolver: &dyn GeoResolver,
+    pending: Vec<(i64, String)>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for (id, ip_address) in pending {
+        let geo = geo::lookup(resolver, &ip_address).await?;
+        database::update_visit_geo(pool, id, &geo).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use axum::{
+    Json,
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::models::{InvalidSlug, InvalidTimeQuery};
+
+/// 接口错误，统一转换为 `{ "success": false, "code": ..., "message": ... }` 响应
+#[derive(Debug)]
+pub enum AppError {
+    /// 项目未登记
+    ProjectNotFound(String),
+    /// 项目已归档，不再接收访问记录
+    ProjectArchived(String),
+    /// 项目标识已被占用
+    ProjectExists(String),
+    /// 项目标识格式不正确
+    InvalidSlug(String),
+    /// 时间查询参数不正确
+    InvalidTimeQuery(String),
+    /// 查询参数或请求体无法解析
+    InvalidRequest(String),
+    /// 缺少或提供了错误的凭证
+    Unauthorized,
+    /// 凭证无权访问请求的资源
+    Forbidden,
+    /// 请求过于频繁，`retry_after` 为建议的重试间隔（秒）
+    RateLimited { retry_after: u64 },
+    /// 数据库读写失败
+    Storage(sqlx::Error),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    success: bool,
+    code: &'static str,
+    message: String,
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::ProjectNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::ProjectArchived(_) => StatusCode::GONE,
+            AppError::ProjectExists(_) => StatusCode::CONFLICT,
+            AppError::InvalidSlug(_)
+            | AppError::InvalidTimeQuery(_)
+            | AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::ProjectNotFound(_) => "project_not_found",
+            AppError::ProjectArchived(_) => "project_archived",
+            AppError::ProjectExists(_) => "project_exists",
+            AppError::InvalidSlug(_) => "invalid_slug",
+            AppError::InvalidTimeQuery(_) => "invalid_time_query",
+            AppError::InvalidRequest(_) => "invalid_request",
+            AppError::Unauthorized => "unauthorized",
+            AppError::Forbidden => "forbidden",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::Storage(_) => "storage_error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            AppError::ProjectNotFound(name) => format!("Project `{}` not found", name),
+            AppError::ProjectArchived(name) => format!("Project `{}` is archived", name),
+            AppError::ProjectExists(name) => format!("Project `{}` already exists", name),
+            AppError::InvalidSlug(slug) => format!(
+                "Invalid project slug `{}`: only lowercase letters, digits, `-` and `_` are allowed",
+                slug
+            ),
+            AppError::InvalidTimeQuery(reason) => format!("Invalid time query: {}", reason),
+            AppError::InvalidRequest(reason) => reason.clone(),
+            AppError::Unauthorized => "Missing or invalid credentials".to_string(),
+            AppError::Forbidden => {
+                "The credentials do not grant access to this resource".to_string()
+            }
+            AppError::RateLimited { retry_after } => {
+                format!("Too many requests, retry after {} seconds", retry_after)
+            }
+            // 不向客户端暴露数据库细节
+            AppError::Storage(_) => "Internal storage error".to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::Storage(e) = &self {
+            error!("请求处理失败: {:?}", e);
+        }
+
+        let body = ErrorResponse {
+            success: false,
+            code: self.code(),
+            message: self.message(),
+        };
+
+        let mut response = (self.status(), Json(body)).into_response();
+        if let AppError::RateLimited { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        response
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Storage(e)
+    }
+}
+
+impl From<InvalidSlug> for AppError {
+    fn from(InvalidSlug(slug): InvalidSlug) -> Self {
+        AppError::InvalidSlug(slug)
+    }
+}
+
+impl From<InvalidTimeQuery> for AppError {
+    fn from(InvalidTimeQuery(reason): InvalidTimeQuery) -> Self {
+        AppError::InvalidTimeQuery(reason)
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::InvalidRequest(rejection.body_text())
+    }
+}
@@ -0,0 +1,64 @@
+//! 解析失败时返回 [`AppError`] 的提取器
+
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, OptionalFromRequest, Request},
+    http::{HeaderMap, header},
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::AppError;
+
+/// 与 [`axum::extract::Query`] 相同，解析失败时返回 JSON 错误
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+/// 与 [`axum::Json`] 相同，解析失败时返回 JSON 错误
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+/// 没有 `Content-Type`、`Content-Type` 不是 JSON 或请求体为空时为 `None`
+///
+/// 早期的客户端不发送请求体，或随请求发送其他类型的内容，这些请求不应因此失败
+impl<T, S> OptionalFromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Option<Self>, Self::Rejection> {
+        if !is_json_content_type(req.headers()) {
+            return Ok(None);
+        }
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|rejection| AppError::InvalidRequest(rejection.body_text()))?;
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        let axum::Json(value) = axum::Json::from_bytes(&body)?;
+
+        Ok(Some(Json(value)))
+    }
+}
+
+/// `Content-Type` 是否为 `application/json` 或 `application/*+json`
+fn is_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
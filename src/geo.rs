@@ -0,0 +1,588 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use maxminddb::{MaxMindDbError, Reader, geoip2};
+use serde::Deserialize;
+
+use crate::config::GeoConfig;
+
+/// 回环地址的国家标签
+pub const LOOPBACK: &str = "Loopback";
+/// 私有网络、运营商级 NAT 及链路本地地址的国家标签
+pub const PRIVATE: &str = "Private";
+/// 未指定、广播、组播、文档示例等保留地址的国家标签
+pub const RESERVED: &str = "Reserved";
+/// 无法解析或查不到地理位置的地址的国家标签
+pub const UNKNOWN: &str = "Unknown";
+
+/// 检查数据库文件是否更新的间隔
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// IP 对应的地理位置
+#[derive(Debug, Clone, Default)]
+pub struct GeoInfo {
+    /// ISO 3166-1 国家代码
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+}
+
+impl GeoInfo {
+    pub fn country(country: &str) -> Self {
+        Self {
+            country: Some(country.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// 地理位置服务暂时不可用，例如网络错误或被限流，稍后重试可能成功
+#[derive(Debug)]
+pub struct GeoError(pub String);
+
+impl fmt::Display for GeoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for GeoError {}
+
+/// IP 地理位置查询
+#[async_trait]
+pub trait GeoResolver: Send + Sync {
+    /// 查询 IP 的地理位置，查不到时返回 `GeoInfo::default()`，服务不可用时返回错误
+    async fn resolve(&self, ip: IpAddr) -> Result<GeoInfo, GeoError>;
+}
+
+/// 可选的地理位置查询后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GeoBackend {
+    /// ip-api.com
+    #[serde(rename = "ip-api")]
+    IpApi,
+    /// 本地 `.mmdb` 文件
+    Mmdb,
+    /// 不查询地理位置
+    None,
+}
+
+impl std::str::FromStr for GeoBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ip-api" | "ipapi" => Ok(GeoBackend::IpApi),
+            "mmdb" => Ok(GeoBackend::Mmdb),
+            "none" | "noop" => Ok(GeoBackend::None),
+            _ => Err(format!("unknown geo backend `{}`", s)),
+        }
+    }
+}
+
+/// 使用 ip-api.com 免费接口查询
+pub struct IpApiResolver {
+    client: reqwest::Client,
+}
+
+impl IpApiResolver {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl GeoResolver for IpApiResolver {
+    async fn resolve(&self, ip: IpAddr) -> Result<GeoInfo, GeoError> {
+        let url = format!("http://ip-api.com/json/{}", ip);
+
+        let data = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| GeoError(format!("ip-api request failed: {}", e)))?
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|e| GeoError(format!("ip-api response is invalid: {}", e)))?;
+
+        let field = |name: &str| {
+            data.get(name)
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+        };
+
+        Ok(GeoInfo {
+            country: field("countryCode"),
+            region: field("regionName"),
+            city: field("city"),
+        })
+    }
+}
+
+/// 不查询地理位置
+pub struct NoopResolver;
+
+#[async_trait]
+impl GeoResolver for NoopResolver {
+    async fn resolve(&self, _ip: IpAddr) -> Result<GeoInfo, GeoError> {
+        Ok(GeoInfo::default())
+    }
+}
+
+/// 返回预设结果，供测试时注入路由状态，避免访问网络
+#[cfg(test)]
+#[derive(Default)]
+pub struct StaticResolver {
+    entries: HashMap<IpAddr, GeoInfo>,
+    fallback: GeoInfo,
+}
+
+#[cfg(test)]
+impl StaticResolver {
+    pub fn new(fallback: GeoInfo) -> Self {
+        Self {
+            entries: HashMap::new(),
+            fallback,
+        }
+    }
+
+    pub fn with(mut self, ip: IpAddr, geo: GeoInfo) -> Self {
+        self.entries.insert(ip, geo);
+        self
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl GeoResolver for StaticResolver {
+    async fn resolve(&self, ip: IpAddr) -> Result<GeoInfo, GeoError> {
+        Ok(self.entries.get(&ip).unwrap_or(&self.fallback).clone())
+    }
+}
+
+/// 带过期时间的查询缓存，查询失败的结果不会被缓存
+pub struct CachedResolver<R> {
+    inner: R,
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<CacheEntries>,
+}
+
+/// 缓存项及按缓存时间排序的索引
+///
+/// 所有缓存项的有效期相同，最早缓存的项最先过期，也是缓存已满时淘汰的项，
+/// 清理和淘汰都只需要从索引开头取出
+#[derive(Default)]
+struct CacheEntries {
+    by_ip: HashMap<IpAddr, (Instant, GeoInfo)>,
+    by_time: BTreeSet<(Instant, IpAddr)>,
+}
+
+impl<R: GeoResolver> CachedResolver<R> {
+    pub fn new(inner: R, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            inner,
+            ttl,
+            capacity,
+            entries: Mutex::new(CacheEntries::default()),
+        }
+    }
+
+    fn get(&self, ip: &IpAddr, now: Instant) -> Option<GeoInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .by_ip
+            .get(ip)
+            .filter(|(cached_at, _)| now.duration_since(*cached_at) < self.ttl)
+            .map(|(_, geo)| geo.clone())
+    }
+
+    fn insert(&self, ip: IpAddr, geo: GeoInfo, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        let CacheEntries { by_ip, by_time } = &mut *entries;
+        if let Some((cached_at, _)) = by_ip.remove(&ip) {
+            by_time.remove(&(cached_at, ip));
+        }
+        // 清理过期项，仍然已满时淘汰最早缓存的项
+        while let Some(&(cached_at, oldest)) = by_time.first()
+            && (now.duration_since(cached_at) >= self.ttl || by_ip.len() >= self.capacity)
+        {
+            by_time.pop_first();
+            by_ip.remove(&oldest);
+        }
+        by_ip.insert(ip, (now, geo));
+        by_time.insert((now, ip));
+    }
+}
+
+#[async_trait]
+impl<R: GeoResolver> GeoResolver for CachedResolver<R> {
+    async fn resolve(&self, ip: IpAddr) -> Result<GeoInfo, GeoError> {
+        if let Some(geo) = self.get(&ip, Instant::now()) {
+            return Ok(geo);
+        }
+
+        let geo = self.inner.resolve(ip).await?;
+        self.insert(ip, geo.clone(), Instant::now());
+
+        Ok(geo)
+    }
+}
+
+#[async_trait]
+impl GeoResolver for Arc<MmdbResolver> {
+    async fn resolve(&self, ip: IpAddr) -> Result<GeoInfo, GeoError> {
+        self.lookup(ip)
+    }
+}
+
+/// 基于本地 MaxMind / DB-IP `.mmdb` 文件的 IP 地理位置查询，文件更新后自动重新加载
+pub struct MmdbResolver {
+    path: PathBuf,
+    reader: RwLock<Arc<Reader<Vec<u8>>>>,
+    modified: RwLock<Option<SystemTime>>,
+}
+
+impl MmdbResolver {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MaxMindDbError> {
+        let path = path.as_ref().to_path_buf();
+        let modified = modified_time(&path);
+        let reader = Reader::open_readfile(&path)?;
+        info!(
+            "GeoIP 数据库加载成功: {} ({})",
+            path.display(),
+            reader.metadata().database_type
+        );
+
+        Ok(Self {
+            path,
+            reader: RwLock::new(Arc::new(reader)),
+            modified: RwLock::new(modified),
+        })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Result<GeoInfo, GeoError> {
+        let reader = self.reader.read().unwrap().clone();
+
+        let city = match reader.lookup(ip).and_then(|r| r.decode::<geoip2::City>()) {
+            Ok(Some(city)) => city,
+            Ok(None) => return Ok(GeoInfo::default()),
+            // 本地数据损坏重试也无济于事，按查不到处理
+            Err(e) => {
+                warn!("GeoIP 查询失败: ip={} error={}", ip, e);
+                return Ok(GeoInfo::default());
+            }
+        };
+
+        Ok(GeoInfo {
+            country: city.country.iso_code.map(str::to_string),
+            region: city
+                .subdivisions
+                .first()
+                .and_then(|s| s.names.english)
+                .map(str::to_string),
+            city: city.city.names.english.map(str::to_string),
+        })
+    }
+
+    /// 文件修改时间变化时重新加载，加载失败则继续使用旧数据
+    fn reload_if_changed(&self) {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == *self.modified.read().unwrap() {
+            return;
+        }
+
+        match Reader::open_readfile(&self.path) {
+            Ok(reader) => {
+                *self.reader.write().unwrap() = Arc::new(reader);
+                *self.modified.write().unwrap() = modified;
+                info!("GeoIP 数据库已重新加载: {}", self.path.display());
+            }
+            Err(e) => {
+                error!("GeoIP 数据库重新加载失败: {:?}", e);
+            }
+        }
+    }
+
+    /// 在后台定期检查数据库文件是否更新
+    pub fn watch(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                let resolver = self.clone();
+                if let Err(e) =
+                    tokio::task::spawn_blocking(move || resolver.reload_if_changed()).await
+                {
+                    error!("GeoIP 数据库检查任务异常: {:?}", e);
+                }
+            }
+        });
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// 根据配置创建地理位置查询服务，除 `None` 外均带有查询缓存
+pub fn build_resolver(
+    config: &GeoConfig,
+) -> Result<Arc<dyn GeoResolver>, Box<dyn std::error::Error>> {
+    let backend = config.backend();
+    let resolver: Arc<dyn GeoResolver> = match backend {
+        GeoBackend::IpApi => Arc::new(CachedResolver::new(
+            IpApiResolver::new(),
+            config.cache_ttl(),
+            config.cache_capacity,
+        )),
+        GeoBackend::Mmdb => {
+            let path = config
+                .mmdb_path
+                .as_deref()
+                .ok_or("mmdb geo backend requires `geo.mmdb_path`")?;
+            let mmdb = Arc::new(MmdbResolver::open(path)?);
+            mmdb.clone().watch();
+            Arc::new(CachedResolver::new(
+                mmdb,
+                config.cache_ttl(),
+                config.cache_capacity,
+            ))
+        }
+        GeoBackend::None => Arc::new(NoopResolver),
+    };
+    info!("地理位置查询后端: {:?}", backend);
+
+    Ok(resolver)
+}
+
+/// 查询 IP 的地理位置，不可公网路由的地址按 [`classify`] 分类，不发送给查询服务，
+/// 无法解析或查不到的地址记为 [`UNKNOWN`]
+///
+/// 只有查询服务不可用时返回错误，调用方可稍后重试
+pub async fn lookup(resolver: &dyn GeoResolver, ip: &str) -> Result<GeoInfo, GeoError> {
+    let Ok(addr) = ip.parse::<IpAddr>() else {
+        return Ok(GeoInfo::country(UNKNOWN));
+    };
+    let addr = addr.to_canonical();
+
+    if let Some(label) = classify(addr) {
+        return Ok(GeoInfo::country(label));
+    }
+
+    let geo = resolver.resolve(addr).await?;
+    if geo.country.is_none() {
+        return Ok(GeoInfo::country(UNKNOWN));
+    }
+
+    Ok(geo)
+}
+
+/// 不可公网路由的地址的类别，公网地址返回 `None`
+///
+/// - 回环：`127.0.0.0/8`、`::1`
+/// - 私有：RFC 1918、RFC 6598（`100.64.0.0/10`）、RFC 4193（`fc00::/7`）及链路本地地址
+/// - 保留：未指定、广播、组播、文档示例及 `0.0.0.0/8`、`240.0.0.0/4`
+fn classify(addr: IpAddr) -> Option<&'static str> {
+    match addr {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            if ip.is_loopback() {
+                Some(LOOPBACK)
+            } else if ip.is_private() || ip.is_link_local() || (a == 100 && b & 0xc0 == 64) {
+                Some(PRIVATE)
+            } else if a == 0
+                || a >= 240
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+            {
+                Some(RESERVED)
+            } else {
+                None
+            }
+        }
+        IpAddr::V6(ip) => {
+            let [first, second, ..] = ip.segments();
+            if ip.is_loopback() {
+                Some(LOOPBACK)
+            } else if ip.is_unique_local() || ip.is_unicast_link_local() {
+                Some(PRIVATE)
+            } else if ip.is_unspecified()
+                || ip.is_multicast()
+                || (first == 0x2001 && second == 0x0db8)
+            {
+                Some(RESERVED)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn classify_str(ip: &str) -> Option<&'static str> {
+        classify(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn classifies_non_routable_addresses() {
+        for (ip, expected) in [
+            ("127.0.0.1", Some(LOOPBACK)),
+            ("127.255.255.254", Some(LOOPBACK)),
+            ("::1", Some(LOOPBACK)),
+            ("10.1.2.3", Some(PRIVATE)),
+            ("172.16.0.1", Some(PRIVATE)),
+            ("172.31.255.255", Some(PRIVATE)),
+            ("192.168.1.1", Some(PRIVATE)),
+            ("169.254.0.1", Some(PRIVATE)),
+            ("100.64.0.1", Some(PRIVATE)),
+            ("100.127.255.255", Some(PRIVATE)),
+            ("fd12:3456::1", Some(PRIVATE)),
+            ("fe80::1", Some(PRIVATE)),
+            ("0.0.0.0", Some(RESERVED)),
+            ("0.1.2.3", Some(RESERVED)),
+            ("255.255.255.255", Some(RESERVED)),
+            ("240.0.0.1", Some(RESERVED)),
+            ("224.0.0.1", Some(RESERVED)),
+            ("192.0.2.1", Some(RESERVED)),
+            ("198.51.100.1", Some(RESERVED)),
+            ("203.0.113.1", Some(RESERVED)),
+            ("::", Some(RESERVED)),
+            ("ff02::1", Some(RESERVED)),
+            ("2001:db8::1", Some(RESERVED)),
+            // 与保留地址段相邻的公网地址
+            ("8.8.8.8", None),
+            ("172.32.0.1", None),
+            ("100.63.255.255", None),
+            ("100.128.0.1", None),
+            ("192.169.0.1", None),
+            ("2001:4860:4860::8888", None),
+            ("2001:db9::1", None),
+        ] {
+            assert_eq!(classify_str(ip), expected, "{ip}");
+        }
+    }
+
+    fn cache(capacity: usize) -> CachedResolver<NoopResolver> {
+        CachedResolver::new(NoopResolver, Duration::from_secs(60), capacity)
+    }
+
+    fn cached(cache: &CachedResolver<NoopResolver>, ip: &str, now: Instant) -> Option<String> {
+        cache.get(&ip.parse().unwrap(), now)?.country
+    }
+
+    fn put(cache: &CachedResolver<NoopResolver>, ip: &str, country: &str, now: Instant) {
+        cache.insert(ip.parse().unwrap(), GeoInfo::country(country), now);
+    }
+
+    #[test]
+    fn full_cache_evicts_oldest_entry() {
+        let cache = cache(2);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        put(&cache, "1.1.1.1", "A", at(0));
+        put(&cache, "2.2.2.2", "B", at(1));
+        // 重新缓存的项按新的时间排序
+        put(&cache, "1.1.1.1", "A2", at(2));
+        put(&cache, "3.3.3.3", "C", at(3));
+
+        assert_eq!(cached(&cache, "1.1.1.1", at(3)).as_deref(), Some("A2"));
+        assert_eq!(cached(&cache, "2.2.2.2", at(3)), None);
+        assert_eq!(cached(&cache, "3.3.3.3", at(3)).as_deref(), Some("C"));
+
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.by_ip.len(), 2);
+        assert_eq!(entries.by_time.len(), 2);
+    }
+
+    #[test]
+    fn expired_entries_are_ignored_and_cleaned() {
+        let cache = cache(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        put(&cache, "1.1.1.1", "A", at(0));
+        put(&cache, "2.2.2.2", "B", at(30));
+        assert_eq!(cached(&cache, "1.1.1.1", at(59)).as_deref(), Some("A"));
+        assert_eq!(cached(&cache, "1.1.1.1", at(60)), None);
+
+        put(&cache, "3.3.3.3", "C", at(60));
+        let entries = cache.entries.lock().unwrap();
+        assert!(!entries.by_ip.contains_key(&"1.1.1.1".parse().unwrap()));
+        assert_eq!(entries.by_ip.len(), 2);
+        assert_eq!(entries.by_time.len(), 2);
+    }
+
+    /// 记录查询次数，查询 `0.0.0.1` 时失败
+    #[derive(Default)]
+    struct CountingResolver(AtomicUsize);
+
+    #[async_trait]
+    impl GeoResolver for CountingResolver {
+        async fn resolve(&self, ip: IpAddr) -> Result<GeoInfo, GeoError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            if ip == IpAddr::from([0, 0, 0, 1]) {
+                return Err(GeoError("unavailable".to_string()));
+            }
+            Ok(GeoInfo::country("US"))
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_skips_failed_lookups() {
+        let cache = CachedResolver::new(CountingResolver::default(), Duration::from_secs(60), 10);
+        let calls = || cache.inner.0.load(Ordering::SeqCst);
+
+        for _ in 0..3 {
+            assert!(cache.resolve("8.8.8.8".parse().unwrap()).await.is_ok());
+        }
+        assert_eq!(calls(), 1);
+
+        for _ in 0..3 {
+            assert!(cache.resolve("0.0.0.1".parse().unwrap()).await.is_err());
+        }
+        assert_eq!(calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn lookup_labels_addresses_without_resolver() {
+        let resolver = StaticResolver::new(GeoInfo::default())
+            .with("8.8.8.8".parse().unwrap(), GeoInfo::country("US"));
+        let country = async |ip: &str| lookup(&resolver, ip).await.unwrap().country;
+
+        assert_eq!(country("8.8.8.8").await.as_deref(), Some("US"));
+        // IPv4 映射地址按 IPv4 地址查询和分类
+        assert_eq!(country("::ffff:8.8.8.8").await.as_deref(), Some("US"));
+        assert_eq!(
+            country("::ffff:192.168.0.1").await.as_deref(),
+            Some(PRIVATE)
+        );
+        assert_eq!(country("1.1.1.1").await.as_deref(), Some(UNKNOWN));
+        assert_eq!(country("unknown").await.as_deref(), Some(UNKNOWN));
+    }
+}
@@ -1,187 +1,895 @@
+use std::collections::HashMap;
+
 use axum::{
-    extract::{Path, Query, State},
-    http::HeaderMap,
-    response::Json,
+    extract::{Path, State},
+    http::StatusCode,
 };
 use serde_json::json;
-use sqlx::SqlitePool;
+use time::{Date, Duration, OffsetDateTime};
 
-use crate::models::{PlatformParams, TimeQuery, TimeQueryParams, TrackResponse};
+use crate::client_ip::ClientIp;
+use crate::config::{BatchConfig, StatsConfig};
+use crate::database::DbPool;
+use crate::enrich::GeoEnricher;
+use crate::error::AppError;
+use crate::extract::{Json, Query};
+use crate::limit::{Decision, TrackLimiter};
+use crate::models::{
+    BatchEvent, BatchItemResult, BatchItemStatus, BatchTrackResponse, DropReason, EventDetail,
+    EventOverview, EventParams, EventSeries, EventSeriesBucket, InvalidTimeQuery,
+    MAX_SERIES_BUCKETS, NewEvent, NewProject, NewVisit, OrphanParams, PlatformBreakdown,
+    ProjectSeries, ProjectSlug, ProjectUpdate, SeriesBucket, SeriesParams, StatsTimeZone,
+    TimeQuery, TimeQueryParams, TimeRange, TrackParams, TrackResponse, VersionAdoption,
+};
+use crate::privacy::{self, IpAnonymizer};
 use crate::{database, models::Project};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn track_visit(
-    Path(project_name): Path<Project>,
-    Query(params): Query<PlatformParams>,
-    State(pool): State<SqlitePool>,
-    headers: HeaderMap,
-) -> Result<Json<TrackResponse>, axum::http::StatusCode> {
-    // 获取客户端IP
-    let ip_address = get_client_ip(&headers);
-
-    // 获取国家信息
-    let country = get_country_from_ip(&ip_address).await;
-
-    // 插入访问记录
-    match database::insert_visit(
-        &pool,
-        &project_name,
-        &params.platform,
-        &ip_address,
-        country.as_deref(),
-    )
-    .await
-    {
-        Ok(_) => Ok(Json(TrackResponse {
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+    State(enricher): State<GeoEnricher>,
+    State(anonymizer): State<IpAnonymizer>,
+    State(limiter): State<TrackLimiter>,
+    client_ip: ClientIp,
+    Query(params): Query<TrackParams>,
+    body: Option<Json<TrackParams>>,
+) -> Result<axum::Json<TrackResponse>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    if project.is_archived() {
+        return Err(AppError::ProjectArchived(project.slug));
+    }
+
+    let params = match body {
+        Some(Json(body)) => params.merge(body),
+        None => params,
+    };
+    let visit = new_visit(params)?;
+
+    let client_ip = client_ip.to_string();
+    let visitor = visitor_key(&visit, &client_ip);
+    if !admit(&limiter, &project, &client_ip, &visitor)? {
+        return Ok(axum::Json(TrackResponse {
             success: true,
-            message: "Visit tracked successfully".to_string(),
-        })),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+            message: "Duplicate visit ignored".to_string(),
+        }));
     }
+
+    let stored = async {
+        // 按配置匿名化后保存
+        let ip = anonymizer.anonymize(&pool, &client_ip).await?;
+        // 插入访问记录，地理位置由后台任务补充
+        database::insert_visits(&pool, &ip, &[(&project, &visit)]).await
+    };
+    if let Err(e) = stored.await {
+        limiter.forget(&project.slug, &visitor);
+        return Err(e.into());
+    }
+    enricher.wake();
+
+    Ok(axum::Json(TrackResponse {
+        success: true,
+        message: "Visit tracked successfully".to_string(),
+    }))
 }
 
-pub async fn get_project_stats(
-    Path(project_name): Path<Project>,
-    State(pool): State<SqlitePool>,
-) -> Result<Json<serde_json::Value>, axum::http::StatusCode> {
-    match database::get_project_detailed_stats(&pool, &project_name).await {
-        Ok(stats) => Ok(Json(json!(stats))),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+/// 上报自定义事件，只限流不去重，同一访客可以多次触发同一事件
+pub async fn track_event(
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+    State(limiter): State<TrackLimiter>,
+    client_ip: ClientIp,
+    Json(params): Json<EventParams>,
+) -> Result<axum::Json<TrackResponse>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    if project.is_archived() {
+        return Err(AppError::ProjectArchived(project.slug));
     }
+
+    let event = new_event(params)?;
+    if let Decision::Limited { retry_after } =
+        limiter.check_event(&project.slug, &client_ip.to_string())
+    {
+        return Err(AppError::RateLimited { retry_after });
+    }
+
+    database::insert_event(&pool, &project, &event).await?;
+
+    Ok(axum::Json(TrackResponse {
+        success: true,
+        message: "Event tracked successfully".to_string(),
+    }))
 }
 
-pub async fn get_all_stats(
-    State(pool): State<SqlitePool>,
-) -> Result<Json<serde_json::Value>, axum::http::StatusCode> {
-    match database::get_all_projects_stats(&pool).await {
-        Ok(stats) => Ok(Json(json!({
-            "projects": stats
-        }))),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+/// 批量上报访问，用于客户端离线后补报，每条访问单独校验并返回结果，接受的访问在同一事务中写入
+///
+/// 每个项目每次请求只消耗一个令牌；带有 `timestamp` 的访问是补报的历史访问，不去重
+#[allow(clippy::too_many_arguments)]
+pub async fn track_batch(
+    State(pool): State<DbPool>,
+    State(enricher): State<GeoEnricher>,
+    State(anonymizer): State<IpAnonymizer>,
+    State(limiter): State<TrackLimiter>,
+    State(config): State<BatchConfig>,
+    client_ip: ClientIp,
+    Json(events): Json<Vec<serde_json::Value>>,
+) -> Result<axum::Json<BatchTrackResponse>, AppError> {
+    if events.len() > config.max_events {
+        return Err(AppError::InvalidRequest(format!(
+            "a batch may contain at most {} events",
+            config.max_events
+        )));
+    }
+
+    let client_ip = client_ip.to_string();
+    let now = OffsetDateTime::now_utc();
+    let mut projects = HashMap::new();
+    let mut accepted = Vec::new();
+    let mut visitors = Vec::new();
+    let mut results = Vec::with_capacity(events.len());
+
+    for event in events {
+        let visit = batch_visit(
+            &pool,
+            &limiter,
+            &config,
+            &mut projects,
+            &client_ip,
+            now,
+            event,
+        );
+        let (status, error) = match visit.await {
+            Ok(Some((project, visit, visitor))) => {
+                if let Some(visitor) = visitor {
+                    visitors.push((project.slug.clone(), visitor));
+                }
+                accepted.push((project, visit));
+                (BatchItemStatus::Accepted, None)
+            }
+            Ok(None) => (BatchItemStatus::Duplicate, None),
+            // 数据库错误时整批失败，客户端可以原样重试
+            Err(AppError::Storage(e)) => {
+                forget_visitors(&limiter, &visitors);
+                return Err(AppError::Storage(e));
+            }
+            Err(e) => (BatchItemStatus::Rejected, Some(e)),
+        };
+        results.push(BatchItemResult {
+            status,
+            code: error.as_ref().map(|e| e.code().to_string()),
+            message: error.as_ref().map(AppError::message),
+        });
+    }
+
+    if !accepted.is_empty() {
+        let stored = async {
+            let ip = anonymizer.anonymize(&pool, &client_ip).await?;
+            let visits: Vec<_> = accepted
+                .iter()
+                .map(|(project, visit)| (project, visit))
+                .collect();
+            database::insert_visits(&pool, &ip, &visits).await
+        };
+        if let Err(e) = stored.await {
+            forget_visitors(&limiter, &visitors);
+            return Err(e.into());
+        }
+        enricher.wake();
     }
+
+    Ok(axum::Json(BatchTrackResponse {
+        success: true,
+        accepted: accepted.len(),
+        results,
+    }))
 }
 
-/// 根据时间查询特定项目的统计数据
-pub async fn get_project_stats_by_time(
-    Path(project_name): Path<Project>,
-    Query(params): Query<TimeQueryParams>,
-    State(pool): State<SqlitePool>,
-) -> Result<Json<serde_json::Value>, axum::http::StatusCode> {
-    // TODO: 当前可能存在问题，等数据多了以后再完善
-    let time = match params.time {
+/// 写入失败时撤销本次请求记录的访客，避免客户端重试被当作重复访问
+fn forget_visitors(limiter: &TrackLimiter, visitors: &[(String, String)]) {
+    for (project, visitor) in visitors {
+        limiter.forget(project, visitor);
+    }
+}
+
+/// 校验批量上报中的一条访问，返回 `None` 表示去重时间内已记录过同一访客，
+/// 否则同时返回去重时记录的访客
+///
+/// `projects` 缓存本次请求中已查询过的项目及其限流结果
+async fn batch_visit(
+    pool: &DbPool,
+    limiter: &TrackLimiter,
+    config: &BatchConfig,
+    projects: &mut HashMap<String, (Project, Decision)>,
+    client_ip: &str,
+    now: OffsetDateTime,
+    event: serde_json::Value,
+) -> Result<Option<(Project, NewVisit, Option<String>)>, AppError> {
+    let event: BatchEvent =
+        serde_json::from_value(event).map_err(|e| AppError::InvalidRequest(e.to_string()))?;
+
+    let project = match projects.get(&event.project) {
+        Some((project, decision)) => {
+            if let Decision::Limited { retry_after } = *decision {
+                limiter.count_dropped(&project.slug, DropReason::RateLimited);
+                return Err(AppError::RateLimited { retry_after });
+            }
+            project.clone()
+        }
         None => {
-            return match database::get_project_detailed_stats(&pool, &project_name).await {
-                Ok(stats) => Ok(Json(json!(stats))),
-                Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+            let project = resolve_project(pool, &event.project).await?;
+            let decision = if project.is_archived() {
+                Decision::Accept
+            } else {
+                limiter.check_rate(&project.slug, client_ip)
             };
+            projects.insert(event.project, (project.clone(), decision));
+            if let Decision::Limited { retry_after } = decision {
+                return Err(AppError::RateLimited { retry_after });
+            }
+            project
         }
-        Some(t) => t,
     };
+    if project.is_archived() {
+        return Err(AppError::ProjectArchived(project.slug));
+    }
 
-    let result = match time {
-        TimeQuery::Date { date } => {
-            database::get_project_stats_by_date(&pool, &project_name, &date).await
-        }
-        TimeQuery::Month { month } => {
-            database::get_project_stats_by_month(&pool, &project_name, &month).await
+    let mut visit = new_visit(event.params)?;
+    if let Some(timestamp) = event.timestamp {
+        if timestamp > now + Duration::seconds(config.max_skew.into()) {
+            return Err(AppError::InvalidRequest(format!(
+                "`timestamp` must not be more than {} seconds in the future",
+                config.max_skew
+            )));
         }
-        TimeQuery::Year { year } => {
-            database::get_project_stats_by_year(&pool, &project_name, &year).await
+        if timestamp < now - Duration::seconds(config.max_age.into()) {
+            return Err(AppError::InvalidRequest(format!(
+                "`timestamp` must not be more than {} seconds in the past",
+                config.max_age
+            )));
         }
-        TimeQuery::Range {
-            start_date,
-            end_date,
-        } => {
-            database::get_project_stats_by_date_range(&pool, &project_name, &start_date, &end_date)
-                .await
+        visit.created_at = Some(timestamp);
+        return Ok(Some((project, visit, None)));
+    }
+
+    let visitor = visitor_key(&visit, client_ip);
+    if limiter.check_duplicate(&project.slug, &visitor) == Decision::Duplicate {
+        return Ok(None);
+    }
+
+    Ok(Some((project, visit, Some(visitor))))
+}
+
+/// 去重时区分访客的依据，优先使用安装标识
+fn visitor_key(visit: &NewVisit, client_ip: &str) -> String {
+    match &visit.install_id {
+        Some(install_id) => format!("i:{}", install_id),
+        None => format!("a:{}", client_ip),
+    }
+}
+
+/// 限流和去重，返回 `false` 表示去重时间内已记录过同一访客
+fn admit(
+    limiter: &TrackLimiter,
+    project: &Project,
+    client_ip: &str,
+    visitor: &str,
+) -> Result<bool, AppError> {
+    match limiter.check(&project.slug, client_ip, visitor) {
+        Decision::Accept => Ok(true),
+        Decision::Duplicate => Ok(false),
+        Decision::Limited { retry_after } => Err(AppError::RateLimited { retry_after }),
+    }
+}
+
+/// 客户端上报的版本等字段最大长度
+const MAX_CLIENT_FIELD_LEN: usize = 64;
+
+/// 校验上报的客户端信息，`platform` 必须提供，其余字段去除首尾空白，空字符串视为未提供
+fn new_visit(params: TrackParams) -> Result<NewVisit, AppError> {
+    let platform = params
+        .platform
+        .ok_or_else(|| AppError::InvalidRequest("missing field `platform`".to_string()))?;
+
+    let field = |name: &str, value: Option<String>| match value.as_deref().map(str::trim) {
+        Some(v) if v.len() > MAX_CLIENT_FIELD_LEN => Err(AppError::InvalidRequest(format!(
+            "`{}` must be at most {} bytes",
+            name, MAX_CLIENT_FIELD_LEN
+        ))),
+        Some(v) if !v.is_empty() => Ok(Some(v.to_string())),
+        _ => Ok(None),
+    };
+
+    Ok(NewVisit {
+        platform,
+        app_version: field("app_version", params.app_version)?,
+        os_version: field("os_version", params.os_version)?,
+        arch: field("arch", params.arch)?.map(|arch| arch.to_ascii_lowercase()),
+        install_id: field("install_id", params.install_id)?.map(|id| privacy::hash_install_id(&id)),
+        created_at: None,
+    })
+}
+
+/// 自定义事件最多包含的属性数量
+const MAX_EVENT_PROPERTIES: usize = 20;
+
+/// 自定义事件属性值的最大长度
+const MAX_PROPERTY_VALUE_LEN: usize = 256;
+
+/// 校验上报的自定义事件，数字和布尔值的属性转换为文本，值为 `null` 的属性忽略
+fn new_event(params: EventParams) -> Result<NewEvent, AppError> {
+    let name = event_identifier("name", &params.name)?;
+
+    if params.properties.len() > MAX_EVENT_PROPERTIES {
+        return Err(AppError::InvalidRequest(format!(
+            "an event may have at most {} properties",
+            MAX_EVENT_PROPERTIES
+        )));
+    }
+    let mut properties = Vec::with_capacity(params.properties.len());
+    for (key, value) in params.properties {
+        let key = event_identifier("property name", &key)?;
+        let value = match value {
+            serde_json::Value::Null => continue,
+            serde_json::Value::String(value) => value,
+            serde_json::Value::Bool(value) => value.to_string(),
+            serde_json::Value::Number(value) => value.to_string(),
+            _ => {
+                return Err(AppError::InvalidRequest(format!(
+                    "property `{}` must be a string, number or boolean",
+                    key
+                )));
+            }
+        };
+        if value.len() > MAX_PROPERTY_VALUE_LEN {
+            return Err(AppError::InvalidRequest(format!(
+                "property `{}` must be at most {} bytes",
+                key, MAX_PROPERTY_VALUE_LEN
+            )));
         }
+        properties.push((key, value));
+    }
+
+    Ok(NewEvent { name, properties })
+}
+
+/// 校验事件名称或属性名称，只允许字母、数字、`_`、`-` 和 `.`
+fn event_identifier(what: &str, value: &str) -> Result<String, AppError> {
+    let valid = !value.is_empty()
+        && value.len() <= MAX_CLIENT_FIELD_LEN
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.');
+
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err(AppError::InvalidRequest(format!(
+            "invalid event {} `{}`, only 1-{} letters, digits, `_`, `-` and `.` are allowed",
+            what, value, MAX_CLIENT_FIELD_LEN
+        )))
+    }
+}
+
+pub async fn get_project_stats(
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+) -> Result<axum::Json<serde_json::Value>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    let stats = database::get_project_detailed_stats(&pool, &project).await?;
+
+    Ok(axum::Json(json!(stats)))
+}
+
+pub async fn get_all_stats(
+    State(pool): State<DbPool>,
+    Query(OrphanParams { orphans }): Query<OrphanParams>,
+) -> Result<axum::Json<serde_json::Value>, AppError> {
+    let stats = database::get_all_projects_stats(&pool, orphans).await?;
+
+    Ok(axum::Json(json!(stats)))
+}
+
+/// 根据时间查询特定项目的统计数据
+pub async fn get_project_stats_by_time(
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+    State(stats_config): State<StatsConfig>,
+    Query(params): Query<TimeQueryParams>,
+) -> Result<axum::Json<serde_json::Value>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+
+    let Some(range) = time_range(&params, &stats_config)? else {
+        let stats = database::get_project_detailed_stats(&pool, &project).await?;
+        return Ok(axum::Json(json!(stats)));
     };
+    let stats = database::get_project_stats_in_range(&pool, &project, range).await?;
+
+    Ok(axum::Json(json!(stats)))
+}
+
+/// 按平台和国家交叉统计项目的访问，可以使用与 `/time` 相同的时间条件
+pub async fn get_platform_breakdown(
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+    State(stats_config): State<StatsConfig>,
+    Query(params): Query<TimeQueryParams>,
+) -> Result<axum::Json<PlatformBreakdown>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    let range = time_range(&params, &stats_config)?;
+    let platforms = database::get_platform_country_stats(&pool, &project, range).await?;
+
+    Ok(axum::Json(PlatformBreakdown {
+        project_name: project.slug,
+        platforms,
+    }))
+}
+
+/// 统计项目各应用版本的使用情况，可以使用与 `/time` 相同的时间条件
+pub async fn get_version_adoption(
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+    State(stats_config): State<StatsConfig>,
+    Query(params): Query<TimeQueryParams>,
+) -> Result<axum::Json<VersionAdoption>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    let range = time_range(&params, &stats_config)?;
+    let versions = database::get_version_stats(&pool, &project, range).await?;
+
+    Ok(axum::Json(VersionAdoption {
+        project_name: project.slug,
+        versions,
+    }))
+}
+
+/// 按天、周或月统计项目的访问趋势
+pub async fn get_project_series(
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+    Query(params): Query<SeriesParams>,
+) -> Result<axum::Json<ProjectSeries>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    let (boundaries, ranges) = series_ranges(&params)?;
+    let counts = database::get_project_series(&pool, &project, &ranges).await?;
+
+    let buckets = boundaries
+        .iter()
+        .zip(counts)
+        .map(|(&start, (total_visits, unique_visitors))| SeriesBucket {
+            start,
+            total_visits: total_visits as u64,
+            unique_visitors: unique_visitors as u64,
+        })
+        .collect();
+
+    Ok(axum::Json(ProjectSeries {
+        project_name: project.slug,
+        interval: params.interval,
+        buckets,
+    }))
+}
+
+/// 统计项目各自定义事件的发生次数，可以使用与 `/time` 相同的时间条件
+pub async fn get_event_overview(
+    Path(project_name): Path<String>,
+    State(pool): State<DbPool>,
+    State(stats_config): State<StatsConfig>,
+    Query(params): Query<TimeQueryParams>,
+) -> Result<axum::Json<EventOverview>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    let range = time_range(&params, &stats_config)?;
+    let events = database::get_event_counts(&pool, &project, range).await?;
+    let (_, rate_limited) = database::get_dropped_visits(&pool, &project).await?;
+
+    Ok(axum::Json(EventOverview {
+        project_name: project.slug,
+        events,
+        rate_limited,
+    }))
+}
+
+/// 每个属性最多返回的取值数量
+const MAX_PROPERTY_VALUES: i64 = 10;
+
+/// 统计单个自定义事件的发生次数及各属性出现次数最多的取值，可以使用与 `/time` 相同的时间条件
+pub async fn get_event_detail(
+    Path((project_name, event_name)): Path<(String, String)>,
+    State(pool): State<DbPool>,
+    State(stats_config): State<StatsConfig>,
+    Query(params): Query<TimeQueryParams>,
+) -> Result<axum::Json<EventDetail>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    let name = event_identifier("name", &event_name)?;
+    let range = time_range(&params, &stats_config)?;
+    let count = database::get_event_count(&pool, &project, &name, range).await?;
+    let properties =
+        database::get_event_property_stats(&pool, &project, &name, range, MAX_PROPERTY_VALUES)
+            .await?;
+
+    Ok(axum::Json(EventDetail {
+        project_name: project.slug,
+        name,
+        count: count as u64,
+        properties,
+    }))
+}
 
-    match result {
-        Ok(stats) => Ok(Json(json!(stats))),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+/// 按天、周或月统计单个自定义事件的发生次数
+pub async fn get_event_series(
+    Path((project_name, event_name)): Path<(String, String)>,
+    State(pool): State<DbPool>,
+    Query(params): Query<SeriesParams>,
+) -> Result<axum::Json<EventSeries>, AppError> {
+    let project = resolve_project(&pool, &project_name).await?;
+    let name = event_identifier("name", &event_name)?;
+    let (boundaries, ranges) = series_ranges(&params)?;
+    let counts = database::get_event_series(&pool, &project, &name, &ranges).await?;
+
+    let buckets = boundaries
+        .iter()
+        .zip(counts)
+        .map(|(&start, count)| EventSeriesBucket {
+            start,
+            count: count as u64,
+        })
+        .collect();
+
+    Ok(axum::Json(EventSeries {
+        project_name: project.slug,
+        name,
+        interval: params.interval,
+        buckets,
+    }))
+}
+
+/// 换算时间序列的时间段，返回每个时间段的第一天及对应的时间范围
+fn series_ranges(params: &SeriesParams) -> Result<(Vec<Date>, Vec<TimeRange>), AppError> {
+    if params.start > params.end {
+        return Err(AppError::InvalidTimeQuery(
+            "start must not be after end".to_string(),
+        ));
     }
+    let boundaries = params
+        .interval
+        .boundaries(params.start, params.end, MAX_SERIES_BUCKETS)
+        .ok_or_else(|| {
+            AppError::InvalidTimeQuery(format!(
+                "a series may contain at most {} buckets",
+                MAX_SERIES_BUCKETS
+            ))
+        })?;
+
+    // 时间段以所选时区的零点为界
+    let tz = StatsTimeZone::from_param(params.tz.as_deref())?;
+    let ranges = boundaries
+        .windows(2)
+        .map(|w| Ok((tz.start_of_day(w[0])?, tz.start_of_day(w[1])?)))
+        .collect::<Result<_, InvalidTimeQuery>>()?;
+
+    Ok((boundaries, ranges))
 }
 
 /// 根据时间查询所有项目的统计数据
 pub async fn get_all_projects_stats_by_time(
+    State(pool): State<DbPool>,
+    State(stats_config): State<StatsConfig>,
     Query(params): Query<TimeQueryParams>,
-    State(pool): State<SqlitePool>,
-) -> Result<Json<serde_json::Value>, axum::http::StatusCode> {
-    // TODO: 当前可能存在问题，等数据多了以后再完善
-    let time = match params.time {
-        None => {
-            return match database::get_all_projects_stats(&pool).await {
-                Ok(stats) => Ok(Json(json!({
-                    "projects": stats
-                }))),
-                Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
-            };
-        }
-        Some(t) => t,
+    Query(OrphanParams { orphans }): Query<OrphanParams>,
+) -> Result<axum::Json<serde_json::Value>, AppError> {
+    let Some(range) = time_range(&params, &stats_config)? else {
+        let stats = database::get_all_projects_stats(&pool, orphans).await?;
+        return Ok(axum::Json(json!(stats)));
     };
+    let stats = database::get_all_projects_stats_in_range(&pool, range, orphans).await?;
 
-    let result = match time {
-        TimeQuery::Date { date } => database::get_all_projects_stats_by_date(&pool, &date).await,
-        TimeQuery::Month { month } => {
-            database::get_all_projects_stats_by_month(&pool, &month).await
-        }
-        TimeQuery::Year { year } => database::get_all_projects_stats_by_year(&pool, &year).await,
-        TimeQuery::Range { .. } => {
-            // 对于范围查询，暂时不支持所有项目的查询，返回错误
-            return Err(axum::http::StatusCode::BAD_REQUEST);
-        }
+    Ok(axum::Json(json!(stats)))
+}
+
+/// 解析时间条件并换算为时间范围，未提供时间条件时返回 `None`
+///
+/// 日期范围包含的天数不能超过配置的上限，其余条件的时间范围是固定的
+fn time_range(
+    params: &TimeQueryParams,
+    config: &StatsConfig,
+) -> Result<Option<TimeRange>, AppError> {
+    let tz = StatsTimeZone::from_param(params.tz.as_deref())?;
+    let Some(time) = params.parse()? else {
+        return Ok(None);
     };
 
-    match result {
-        Ok(stats) => Ok(Json(json!({
-            "projects": stats
-        }))),
-        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+    if let TimeQuery::Range { start, end } = time {
+        let days = (end - start).whole_days() + 1;
+        if days > i64::from(config.max_range_days) {
+            return Err(AppError::InvalidTimeQuery(format!(
+                "date range spans {} days, at most {} are allowed",
+                days, config.max_range_days
+            )));
+        }
     }
+
+    Ok(Some(time.bounds(tz)?))
 }
 
-fn get_client_ip(headers: &HeaderMap) -> String {
-    // 尝试从各种可能的头部获取真实IP
-    if let Some(ip) = headers.get("x-forwarded-for")
-        && let Ok(ip_str) = ip.to_str()
-    {
-        return ip_str
-            .split(',')
-            .next()
-            .unwrap_or("unknown")
-            .trim()
-            .to_string();
+/// 列出所有项目，包括已归档的项目
+pub async fn list_projects(
+    State(pool): State<DbPool>,
+) -> Result<axum::Json<Vec<Project>>, AppError> {
+    let projects = database::list_projects(&pool).await?;
+
+    Ok(axum::Json(projects))
+}
+
+/// 登记新项目
+pub async fn create_project(
+    Path(slug): Path<String>,
+    State(pool): State<DbPool>,
+    Json(project): Json<NewProject>,
+) -> Result<(StatusCode, axum::Json<Project>), AppError> {
+    let slug: ProjectSlug = slug.parse()?;
+    if slug.is_reserved() {
+        return Err(AppError::InvalidRequest(format!(
+            "project slug `{}` is reserved",
+            slug
+        )));
     }
 
-    if let Some(ip) = headers.get("x-real-ip")
-        && let Ok(ip_str) = ip.to_str()
-    {
-        return ip_str.to_string();
+    match database::create_project(&pool, &slug, &project).await? {
+        Some(project) => {
+            info!("项目`{}`已创建", project.slug);
+            Ok((StatusCode::CREATED, axum::Json(project)))
+        }
+        None => Err(AppError::ProjectExists(slug.to_string())),
     }
+}
+
+/// 更新项目的仓库地址、图标、描述或归档状态
+pub async fn update_project(
+    Path(slug): Path<String>,
+    State(pool): State<DbPool>,
+    Json(update): Json<ProjectUpdate>,
+) -> Result<axum::Json<Project>, AppError> {
+    let slug: ProjectSlug = slug.parse()?;
+
+    database::update_project(&pool, &slug, &update)
+        .await?
+        .map(axum::Json)
+        .ok_or_else(|| AppError::ProjectNotFound(slug.to_string()))
+}
+
+/// 归档项目，历史数据仍可通过统计接口查询
+pub async fn archive_project(
+    Path(slug): Path<String>,
+    State(pool): State<DbPool>,
+) -> Result<axum::Json<Project>, AppError> {
+    let slug: ProjectSlug = slug.parse()?;
+    let project = database::archive_project(&pool, &slug)
+        .await?
+        .ok_or_else(|| AppError::ProjectNotFound(slug.to_string()))?;
+    info!("项目`{}`已归档", project.slug);
+
+    Ok(axum::Json(project))
+}
+
+/// 根据路由中的项目名称查找已登记的项目
+async fn resolve_project(pool: &DbPool, project_name: &str) -> Result<Project, AppError> {
+    let slug: ProjectSlug = project_name.parse()?;
 
-    "unknown".to_string()
+    database::get_project(pool, &slug)
+        .await?
+        .ok_or_else(|| AppError::ProjectNotFound(slug.to_string()))
 }
 
-async fn get_country_from_ip(ip: &str) -> Option<String> {
-    if ip == "unknown" || ip.starts_with("127.") || ip.starts_with("192.168.") {
-        return Some("Local".to_string());
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::Value;
+    use time::format_description::well_known::Rfc3339;
+
+    use super::*;
+    use crate::config::RateLimitConfig;
+    use crate::geo::NoopResolver;
+    use crate::privacy::IpMode;
+
+    struct Batch {
+        pool: DbPool,
+        limiter: TrackLimiter,
+        config: BatchConfig,
     }
 
-    // 使用免费的IP地理位置API
-    let client = reqwest::Client::new();
-    let url = format!("http://ip-api.com/json/{}", ip);
+    impl Batch {
+        async fn new(rate_limit: RateLimitConfig) -> Self {
+            let pool = database::init_database("sqlite::memory:")
+                .await
+                .expect("in-memory sqlite should initialize");
+            let limiter = TrackLimiter::spawn(pool.clone(), rate_limit);
+            let config = BatchConfig {
+                max_events: 5,
+                max_age: 60 * 60,
+                max_skew: 60,
+            };
 
-    match client.get(&url).send().await {
-        Ok(response) => {
-            if let Ok(data) = response.json::<serde_json::Value>().await {
-                data.get("countryCode")
-                    .and_then(|c| c.as_str())
-                    .map(|s| s.to_string())
-                    .or_else(|| Some("Unknown".to_string()))
-            } else {
-                Some("Unknown".to_string())
+            Self {
+                pool,
+                limiter,
+                config,
             }
         }
-        Err(_) => Some("Unknown".to_string()),
+
+        /// 提交一批访问，返回每条访问的状态，被拒绝时为错误代码
+        async fn send(&self, events: Vec<Value>) -> Result<Vec<String>, AppError> {
+            let response = track_batch(
+                State(self.pool.clone()),
+                State(GeoEnricher::spawn(
+                    self.pool.clone(),
+                    Arc::new(NoopResolver),
+                )),
+                State(IpAnonymizer::new(IpMode::Raw)),
+                State(self.limiter.clone()),
+                State(self.config.clone()),
+                ClientIp(Some("203.0.113.9".parse().unwrap())),
+                Json(events),
+            )
+            .await?;
+            let outcomes: Vec<_> = response
+                .results
+                .iter()
+                .map(|result| match &result.code {
+                    Some(code) => code.clone(),
+                    None => serde_json::to_value(result.status)
+                        .unwrap()
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                })
+                .collect();
+            assert_eq!(
+                response.accepted,
+                outcomes.iter().filter(|o| *o == "accepted").count()
+            );
+
+            Ok(outcomes)
+        }
+
+        /// 在 `now` 时刻校验一条访问，返回访问时间
+        async fn check_at(
+            &self,
+            now: OffsetDateTime,
+            timestamp: OffsetDateTime,
+        ) -> Result<Option<OffsetDateTime>, AppError> {
+            let event = json!({
+                "project": "dwall",
+                "platform": "linux",
+                "timestamp": timestamp.format(&Rfc3339).unwrap(),
+            });
+            let visit = batch_visit(
+                &self.pool,
+                &self.limiter,
+                &self.config,
+                &mut HashMap::new(),
+                "203.0.113.9",
+                now,
+                event,
+            )
+            .await?;
+
+            Ok(visit.and_then(|(_, visit, _)| visit.created_at))
+        }
+    }
+
+    fn visit(project: &str, install_id: &str) -> Value {
+        json!({ "project": project, "platform": "linux", "install_id": install_id })
+    }
+
+    fn visit_at(offset: Duration) -> Value {
+        let timestamp = OffsetDateTime::now_utc() + offset;
+        json!({
+            "project": "dwall",
+            "platform": "linux",
+            "install_id": "a",
+            "timestamp": timestamp.format(&Rfc3339).unwrap(),
+        })
+    }
+
+    #[tokio::test]
+    async fn timestamps_must_be_within_skew_window() {
+        let batch = Batch::new(RateLimitConfig::default()).await;
+        let now = OffsetDateTime::now_utc();
+        let max_skew = Duration::seconds(batch.config.max_skew.into());
+        let max_age = Duration::seconds(batch.config.max_age.into());
+
+        for timestamp in [now, now + max_skew, now - max_age] {
+            assert_eq!(
+                batch.check_at(now, timestamp).await.unwrap(),
+                Some(timestamp)
+            );
+        }
+        for timestamp in [
+            now + max_skew + Duration::SECOND,
+            now - max_age - Duration::SECOND,
+        ] {
+            assert!(matches!(
+                batch.check_at(now, timestamp).await,
+                Err(AppError::InvalidRequest(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn items_are_validated_individually() {
+        let batch = Batch::new(RateLimitConfig::default()).await;
+        let outcomes = batch
+            .send(vec![
+                visit("dwall", "a"),
+                json!({ "project": "dwall" }),
+                json!("not an object"),
+                visit("missing", "a"),
+                visit_at(Duration::days(1)),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            outcomes,
+            [
+                "accepted",
+                "invalid_request",
+                "invalid_request",
+                "project_not_found",
+                "invalid_request"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn timestamped_items_skip_dedup() {
+        let batch = Batch::new(RateLimitConfig {
+            dedup_window: 60,
+            ..Default::default()
+        })
+        .await;
+        let outcomes = batch
+            .send(vec![
+                visit("dwall", "a"),
+                visit("dwall", "a"),
+                visit_at(-Duration::minutes(10)),
+                visit_at(-Duration::minutes(20)),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(outcomes, ["accepted", "duplicate", "accepted", "accepted"]);
+    }
+
+    #[tokio::test]
+    async fn batch_takes_one_token_per_project() {
+        let batch = Batch::new(RateLimitConfig {
+            per_minute: 1,
+            burst: 1,
+            dedup_window: 0,
+        })
+        .await;
+        let outcomes = batch
+            .send(vec![
+                visit("dwall", "a"),
+                visit("dwall", "b"),
+                visit("lsar", "a"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(outcomes, ["accepted", "accepted", "accepted"]);
+
+        let outcomes = batch
+            .send(vec![visit("dwall", "c"), visit("dwall", "d")])
+            .await
+            .unwrap();
+        assert_eq!(outcomes, ["rate_limited", "rate_limited"]);
+    }
+
+    #[tokio::test]
+    async fn oversized_batch_is_rejected() {
+        let batch = Batch::new(RateLimitConfig::default()).await;
+        let events = (0..6).map(|i| visit("dwall", &i.to_string())).collect();
+        assert!(matches!(
+            batch.send(events).await,
+            Err(AppError::InvalidRequest(_))
+        ));
     }
 }
@@ -0,0 +1,402 @@
+//! `/track` 的限流和去重，自定义事件单独限流，状态保存在内存中，未计入的访问次数定期写入数据库
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::RateLimitConfig,
+    database::{self, DbPool},
+    models::DropReason,
+};
+
+/// 写入未计入的访问次数并清理过期状态的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 一次访问的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// 记录这次访问
+    Accept,
+    /// 去重时间内已记录过同一访客
+    Duplicate,
+    /// 超出速率限制，`retry_after` 为建议的重试间隔（秒）
+    Limited { retry_after: u64 },
+}
+
+/// 令牌桶，每次访问消耗一个令牌，令牌按配置的速率恢复
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    /// 访问的令牌桶，以 `(项目, IP 地址)` 为键
+    buckets: HashMap<(String, String), Bucket>,
+    /// 自定义事件的令牌桶，与访问分开计算，事件再多也不影响访问的记录
+    event_buckets: HashMap<(String, String), Bucket>,
+    /// 以 `(项目, 访客)` 为键，值为最近一次记录访问的时间
+    seen: HashMap<(String, String), Instant>,
+    /// 尚未写入数据库的未计入次数
+    dropped: HashMap<(String, DropReason), i64>,
+}
+
+/// 限流器的句柄，克隆后共享状态
+#[derive(Clone)]
+pub struct TrackLimiter {
+    config: RateLimitConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl TrackLimiter {
+    pub fn spawn(pool: DbPool, config: RateLimitConfig) -> Self {
+        let limiter = Self {
+            config,
+            state: Arc::new(Mutex::new(State::default())),
+        };
+        tokio::spawn(limiter.clone().run(pool));
+
+        limiter
+    }
+
+    /// 检查 `ip` 对 `project` 的访问，`visitor` 为区分访客的依据
+    pub fn check(&self, project: &str, ip: &str, visitor: &str) -> Decision {
+        match self.check_rate(project, ip) {
+            Decision::Accept => self.check_duplicate(project, visitor),
+            limited => limited,
+        }
+    }
+
+    /// 只检查速率限制，批量上报时每个项目只消耗一个令牌
+    pub fn check_rate(&self, project: &str, ip: &str) -> Decision {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            buckets, dropped, ..
+        } = &mut *state;
+
+        match self.take_token(buckets, project, ip, Instant::now()) {
+            Some(retry_after) => {
+                *dropped
+                    .entry((project.to_string(), DropReason::RateLimited))
+                    .or_default() += 1;
+                Decision::Limited { retry_after }
+            }
+            None => Decision::Accept,
+        }
+    }
+
+    /// 只检查去重，未重复时记录该访客
+    pub fn check_duplicate(&self, project: &str, visitor: &str) -> Decision {
+        if self.config.dedup_window == 0 {
+            return Decision::Accept;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.dedup_window);
+        let key = (project.to_string(), visitor.to_string());
+        let mut state = self.state.lock().unwrap();
+        if let Some(&seen_at) = state.seen.get(&key)
+            && now.duration_since(seen_at) < window
+        {
+            drop(state);
+            self.count_dropped(project, DropReason::Duplicate);
+            return Decision::Duplicate;
+        }
+        state.seen.insert(key, now);
+
+        Decision::Accept
+    }
+
+    /// 累加未计入的次数，由定期任务写入数据库
+    pub fn count_dropped(&self, project: &str, reason: DropReason) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .dropped
+            .entry((project.to_string(), reason))
+            .or_default() += 1;
+    }
+
+    /// 撤销 [`check`](Self::check) 或 [`check_duplicate`](Self::check_duplicate) 记录的访客，用于访问记录写入失败时，避免客户端重试被当作重复访问
+    pub fn forget(&self, project: &str, visitor: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .seen
+            .remove(&(project.to_string(), visitor.to_string()));
+    }
+
+    /// 检查自定义事件的速率限制，不去重，使用与访问相同的限额但单独计算
+    pub fn check_event(&self, project: &str, ip: &str) -> Decision {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            event_buckets,
+            dropped,
+            ..
+        } = &mut *state;
+
+        match self.take_token(event_buckets, project, ip, Instant::now()) {
+            Some(retry_after) => {
+                *dropped
+                    .entry((project.to_string(), DropReason::EventRateLimited))
+                    .or_default() += 1;
+                Decision::Limited { retry_after }
+            }
+            None => Decision::Accept,
+        }
+    }
+
+    /// 从 `(project, ip)` 的令牌桶中取出一个令牌，超出速率限制时返回建议的重试间隔（秒）
+    fn take_token(
+        &self,
+        buckets: &mut HashMap<(String, String), Bucket>,
+        project: &str,
+        ip: &str,
+        now: Instant,
+    ) -> Option<u64> {
+        if self.config.per_minute == 0 {
+            return None;
+        }
+
+        let capacity = self.capacity();
+        let bucket = buckets
+            .entry((project.to_string(), ip.to_string()))
+            .or_insert(Bucket {
+                tokens: capacity,
+                updated_at: now,
+            });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens < 1.0 {
+            return Some(((1.0 - bucket.tokens) / self.rate()).ceil() as u64);
+        }
+        bucket.tokens -= 1.0;
+
+        None
+    }
+
+    /// 令牌桶容量，至少为 1
+    fn capacity(&self) -> f64 {
+        f64::from(self.config.burst.max(1))
+    }
+
+    /// 每秒恢复的令牌数
+    fn rate(&self) -> f64 {
+        f64::from(self.config.per_minute) / 60.0
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate()).min(self.capacity())
+    }
+
+    async fn run(self, pool: DbPool) {
+        let mut ticker = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            ticker.tick().await;
+            self.flush(&pool).await;
+        }
+    }
+
+    /// 写入未计入的访问次数，并清理已恢复满的令牌桶和超出去重时间的访客
+    async fn flush(&self, pool: &DbPool) {
+        let dropped = {
+            let now = Instant::now();
+            let window = Duration::from_secs(self.config.dedup_window);
+            let mut state = self.state.lock().unwrap();
+            let State {
+                buckets,
+                event_buckets,
+                seen,
+                dropped,
+            } = &mut *state;
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.capacity());
+            event_buckets.retain(|_, bucket| self.refill(bucket, now) < self.capacity());
+            seen.retain(|_, seen_at| now.duration_since(*seen_at) < window);
+            std::mem::take(dropped)
+        };
+
+        for ((project, reason), count) in dropped {
+            if database::record_dropped_visits(pool, &project, reason, count)
+                .await
+                .is_err()
+            {
+                // 留到下一轮写入
+                *self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .dropped
+                    .entry((project, reason))
+                    .or_default() += count;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ProjectSlug;
+
+    fn limiter(per_minute: u32, burst: u32, dedup_window: u64) -> TrackLimiter {
+        TrackLimiter {
+            config: RateLimitConfig {
+                per_minute,
+                burst,
+                dedup_window,
+            },
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// 在 `now` 时刻从 `("demo", ip)` 的令牌桶中取令牌
+    fn take(limiter: &TrackLimiter, ip: &str, now: Instant) -> Option<u64> {
+        let mut state = limiter.state.lock().unwrap();
+        limiter.take_token(&mut state.buckets, "demo", ip, now)
+    }
+
+    fn dropped(limiter: &TrackLimiter, reason: DropReason) -> i64 {
+        let state = limiter.state.lock().unwrap();
+        state
+            .dropped
+            .get(&("demo".to_string(), reason))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let limiter = limiter(60, 3, 0);
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+
+        for _ in 0..3 {
+            assert_eq!(take(&limiter, "1.1.1.1", start), None);
+        }
+        assert_eq!(take(&limiter, "1.1.1.1", start), Some(1));
+        // 其他 IP 地址的令牌桶不受影响
+        assert_eq!(take(&limiter, "2.2.2.2", start), None);
+
+        assert_eq!(take(&limiter, "1.1.1.1", at(0.5)), Some(1));
+        assert_eq!(take(&limiter, "1.1.1.1", at(1.0)), None);
+        assert_eq!(take(&limiter, "1.1.1.1", at(1.0)), Some(1));
+
+        // 恢复的令牌不超过容量
+        for _ in 0..3 {
+            assert_eq!(take(&limiter, "1.1.1.1", at(100.0)), None);
+        }
+        assert_eq!(take(&limiter, "1.1.1.1", at(100.0)), Some(1));
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        // 每分钟 7 次，恢复一个令牌需要约 8.57 秒
+        let limiter = limiter(7, 1, 0);
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+
+        assert_eq!(take(&limiter, "1.1.1.1", start), None);
+        assert_eq!(take(&limiter, "1.1.1.1", start), Some(9));
+        assert_eq!(take(&limiter, "1.1.1.1", at(8.0)), Some(1));
+        assert_eq!(take(&limiter, "1.1.1.1", at(8.6)), None);
+    }
+
+    #[test]
+    fn zero_rate_disables_and_zero_burst_allows_one() {
+        let unlimited = limiter(0, 0, 0);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(take(&unlimited, "1.1.1.1", now), None);
+        }
+
+        let single = limiter(60, 0, 0);
+        assert_eq!(take(&single, "1.1.1.1", now), None);
+        assert_eq!(take(&single, "1.1.1.1", now), Some(1));
+    }
+
+    #[test]
+    fn events_use_separate_buckets_and_counters() {
+        let limiter = limiter(60, 1, 0);
+        assert_eq!(limiter.check_rate("demo", "1.1.1.1"), Decision::Accept);
+        assert!(matches!(
+            limiter.check_rate("demo", "1.1.1.1"),
+            Decision::Limited { .. }
+        ));
+        assert_eq!(limiter.check_event("demo", "1.1.1.1"), Decision::Accept);
+        assert!(matches!(
+            limiter.check_event("demo", "1.1.1.1"),
+            Decision::Limited { .. }
+        ));
+        assert!(matches!(
+            limiter.check_event("demo", "1.1.1.1"),
+            Decision::Limited { .. }
+        ));
+
+        assert_eq!(dropped(&limiter, DropReason::RateLimited), 1);
+        assert_eq!(dropped(&limiter, DropReason::EventRateLimited), 2);
+    }
+
+    #[test]
+    fn duplicates_within_window_are_dropped() {
+        let limiter = limiter(0, 0, 60);
+        assert_eq!(limiter.check("demo", "1.1.1.1", "i:a"), Decision::Accept);
+        assert_eq!(limiter.check("demo", "2.2.2.2", "i:a"), Decision::Duplicate);
+        assert_eq!(limiter.check("demo", "1.1.1.1", "i:b"), Decision::Accept);
+        assert_eq!(limiter.check("other", "1.1.1.1", "i:a"), Decision::Accept);
+        assert_eq!(dropped(&limiter, DropReason::Duplicate), 1);
+
+        // 写入失败后撤销，客户端重试时不算重复
+        limiter.forget("demo", "i:a");
+        assert_eq!(limiter.check("demo", "1.1.1.1", "i:a"), Decision::Accept);
+    }
+
+    #[test]
+    fn zero_window_disables_dedup() {
+        let limiter = limiter(0, 0, 0);
+        assert_eq!(limiter.check("demo", "1.1.1.1", "i:a"), Decision::Accept);
+        assert_eq!(limiter.check("demo", "1.1.1.1", "i:a"), Decision::Accept);
+        assert_eq!(dropped(&limiter, DropReason::Duplicate), 0);
+    }
+
+    #[test]
+    fn limited_visits_are_not_remembered() {
+        let limiter = limiter(60, 1, 60);
+        assert_eq!(limiter.check("demo", "1.1.1.1", "i:a"), Decision::Accept);
+        assert!(matches!(
+            limiter.check("demo", "1.1.1.1", "i:b"),
+            Decision::Limited { .. }
+        ));
+        // 被限流的访客没有被记录，之后不会被当作重复访问
+        assert_eq!(limiter.check("demo", "2.2.2.2", "i:b"), Decision::Accept);
+    }
+
+    #[tokio::test]
+    async fn flush_writes_dropped_counts() {
+        let pool = database::init_database("sqlite::memory:")
+            .await
+            .expect("in-memory sqlite should initialize");
+        let project: ProjectSlug = "dwall".parse().unwrap();
+        let project = database::get_project(&pool, &project)
+            .await
+            .unwrap()
+            .expect("seeded project should exist");
+
+        let limiter = limiter(60, 1, 60);
+        limiter.count_dropped(&project.slug, DropReason::RateLimited);
+        limiter.count_dropped(&project.slug, DropReason::RateLimited);
+        limiter.count_dropped(&project.slug, DropReason::Duplicate);
+        limiter.flush(&pool).await;
+        limiter.flush(&pool).await;
+
+        let (visits, events) = database::get_dropped_visits(&pool, &project).await.unwrap();
+        assert_eq!((visits.rate_limited, visits.duplicate, events), (2, 1, 0));
+        assert!(limiter.state.lock().unwrap().dropped.is_empty());
+    }
+}
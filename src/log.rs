@@ -1,9 +1,9 @@
-use std::env;
+use std::{env, path::Path};
 
 use tracing::Level;
 use tracing_subscriber::fmt::writer::MakeWriterExt;
 
-pub fn init() {
+pub fn init(directory: &Path) {
     // 从环境变量获取日志级别，如果未设置则根据环境使用默认值
     let log_level = env::var("RUST_LOG").unwrap_or_else(|_| {
         if cfg!(debug_assertions) {
@@ -30,7 +30,7 @@ pub fn init() {
         // 生产环境：输出到文件
         let file_appender = tracing_appender::rolling::RollingFileAppender::new(
             tracing_appender::rolling::Rotation::DAILY,
-            "log",
+            directory,
             "server",
         );
 
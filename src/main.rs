@@ -1,52 +1,292 @@
 use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
 
 use axum::{
+    Router, middleware,
     routing::{get, post},
-    Router,
 };
+use clap::Parser;
 use tower_http::cors::CorsLayer;
 
+use crate::client_ip::TrustedProxies;
+use crate::config::{ApiKeyCommand, BindAddress, Cli, Command, Config};
+use crate::enrich::GeoEnricher;
+use crate::limit::TrackLimiter;
+use crate::models::ProjectSlug;
+use crate::privacy::IpAnonymizer;
+use crate::state::{AdminToken, AppState, StatsAuth};
+
 #[macro_use]
 extern crate tracing;
 
+mod auth;
+mod client_ip;
+mod config;
 mod database;
+mod enrich;
+mod error;
+mod extract;
+mod geo;
 mod handlers;
+mod limit;
 mod log;
+mod migrations;
 mod models;
+mod privacy;
+mod retention;
+mod state;
+#[cfg(test)]
+mod tests;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let config = Config::load(&cli)?;
+
     // 初始化日志
-    log::init();
+    log::init(&config.log.directory);
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::Migrate { dry_run } => migrate(config, dry_run).await,
+        Command::ApiKey { command } => api_key(config, command).await,
+    }
+}
+
+/// 执行数据库迁移后退出
+async fn migrate(config: Config, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let pool = database::connect(&config.database.url).await?;
+    let current = migrations::current_version(&pool).await?;
+    let migrations = migrations::run(&pool, dry_run).await?;
+
+    println!(
+        "schema version: {} (latest: {})",
+        current,
+        migrations::latest_version()
+    );
+    if migrations.is_empty() {
+        println!("database is up to date");
+    }
+    for migration in migrations {
+        let action = if dry_run { "pending" } else { "applied" };
+        println!(
+            "{} {:04} {}",
+            action, migration.version, migration.description
+        );
+    }
+
+    Ok(())
+}
+
+/// 创建、列出或吊销 API key 后退出
+async fn api_key(config: Config, command: ApiKeyCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let pool = database::init_database(&config.database.url).await?;
+
+    match command {
+        ApiKeyCommand::Create { name, project } => {
+            let project = match project {
+                Some(project) => {
+                    let slug: ProjectSlug = project
+                        .parse()
+                        .map_err(|_| format!("invalid project slug `{}`", project))?;
+                    if database::get_project(&pool, &slug).await?.is_none() {
+                        return Err(format!("project `{}` not found", slug).into());
+                    }
+                    Some(slug)
+                }
+                None => None,
+            };
+
+            let (key, key_hash) = auth::generate_api_key();
+            let api_key =
+                database::create_api_key(&pool, &name, &key_hash, project.as_ref()).await?;
+            println!("created api key {} ({})", api_key.id, api_key.name);
+            println!("{}", key);
+        }
+        ApiKeyCommand::List => {
+            for key in database::list_api_keys(&pool).await? {
+                let scope = key.project_name.as_deref().unwrap_or("*");
+                let status = if key.revoked_at.is_some() {
+                    "revoked"
+                } else {
+                    "active"
+                };
+                println!("{}\t{}\t{}\t{}", key.id, key.name, scope, status);
+            }
+        }
+        ApiKeyCommand::Revoke { id } => match database::revoke_api_key(&pool, id).await? {
+            Some(key) => println!("revoked api key {} ({})", key.id, key.name),
+            None => return Err(format!("api key {} not found", id).into()),
+        },
+    }
 
+    Ok(())
+}
+
+async fn serve(config: Config) -> Result<(), Box<dyn std::error::Error>> {
     // 初始化数据库连接池
-    let pool = database::init_database().await.map_err(|e| {
-        error!("数据库初始化失败: {:?}", e);
+    let pool = database::init_database(&config.database.url)
+        .await
+        .map_err(|e| {
+            error!("数据库初始化失败: {:?}", e);
+            e
+        })?;
+
+    // 初始化地理位置查询
+    let geo = geo::build_resolver(&config.geo).map_err(|e| {
+        error!("地理位置查询初始化失败: {:?}", e);
         e
     })?;
 
-    // 构建路由
-    let app = Router::new()
+    let admin_token = match &config.admin.token {
+        Some(token) => Some(AdminToken(token.as_str().into())),
+        None => {
+            warn!("未设置管理令牌，管理接口已禁用");
+            None
+        }
+    };
+
+    let stats_auth = if config.stats.public {
+        warn!("统计接口未启用鉴权");
+        None
+    } else {
+        Some(StatsAuth {
+            pool: pool.clone(),
+            admin_token: admin_token.clone(),
+        })
+    };
+
+    info!("IP 地址保存方式: {}", config.privacy.ip_mode);
+    let enricher = GeoEnricher::spawn(pool.clone(), geo);
+    retention::spawn(pool.clone(), config.retention.interval());
+    let limiter = TrackLimiter::spawn(pool.clone(), config.rate_limit.clone());
+    let app = router(
+        AppState {
+            pool,
+            enricher,
+            stats: config.stats.clone(),
+            anonymizer: IpAnonymizer::new(config.privacy.ip_mode),
+            limiter,
+            trusted_proxies: TrustedProxies::new(
+                &config.server.trusted_proxies,
+                config.server.forwarded_header,
+            ),
+            batch: config.batch.clone(),
+        },
+        stats_auth,
+        admin_token,
+    );
+
+    // 启动服务器
+    let bind = &config.server.bind;
+    match bind {
+        BindAddress::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+                error!("服务器绑定失败: {:?}", e);
+                e
+            })?;
+            info!("服务器运行在 {}", bind);
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await?;
+        }
+        #[cfg(unix)]
+        BindAddress::Unix(path) => {
+            // 清理上次运行遗留的套接字文件，不是套接字时拒绝启动，避免误删其他文件
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if !metadata.file_type().is_socket() {
+                    error!("{} 已存在且不是套接字", path.display());
+                    return Err(format!("`{}` exists and is not a socket", path.display()).into());
+                }
+                std::fs::remove_file(path)?;
+            }
+            let listener = tokio::net::UnixListener::bind(path).map_err(|e| {
+                error!("服务器绑定失败: {:?}", e);
+                e
+            })?;
+            info!("服务器运行在 {}", bind);
+            axum::serve(listener, app).await?;
+        }
+        #[cfg(not(unix))]
+        BindAddress::Unix(_) => {
+            return Err("unix sockets are not supported on this platform".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// 构建路由，状态由调用方注入，便于在测试中替换数据库和地理位置查询
+fn router(
+    state: AppState,
+    stats_auth: Option<StatsAuth>,
+    admin_token: Option<AdminToken>,
+) -> Router {
+    Router::new()
+        .route("/track/batch", post(handlers::track_batch))
         .route("/track/{project_name}", post(handlers::track_visit))
+        .route("/track/{project_name}/event", post(handlers::track_event))
+        .merge(stats_router(stats_auth))
+        .merge(admin_token.map(admin_router).unwrap_or_default())
+        .layer(CorsLayer::permissive())
+        .with_state(state)
+}
+
+/// 统计接口，`auth` 为 `None` 时公开访问
+fn stats_router(auth: Option<StatsAuth>) -> Router<AppState> {
+    let router = Router::new()
         .route("/stats/{project_name}", get(handlers::get_project_stats))
         .route("/stats", get(handlers::get_all_stats))
         .route(
             "/stats/{project_name}/time",
             get(handlers::get_project_stats_by_time),
         )
-        .route("/stats/time", get(handlers::get_all_projects_stats_by_time))
-        .layer(CorsLayer::permissive())
-        .with_state(pool);
-
-    // 启动服务器
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3162));
-    let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
-        error!("服务器绑定失败: {:?}", e);
-        e
-    })?;
-    info!("服务器运行在 http://{}", addr);
+        .route(
+            "/stats/{project_name}/platforms",
+            get(handlers::get_platform_breakdown),
+        )
+        .route(
+            "/stats/{project_name}/versions",
+            get(handlers::get_version_adoption),
+        )
+        .route(
+            "/stats/{project_name}/series",
+            get(handlers::get_project_series),
+        )
+        .route(
+            "/stats/{project_name}/events",
+            get(handlers::get_event_overview),
+        )
+        .route(
+            "/stats/{project_name}/events/{event_name}",
+            get(handlers::get_event_detail),
+        )
+        .route(
+            "/stats/{project_name}/events/{event_name}/series",
+            get(handlers::get_event_series),
+        )
+        .route("/stats/time", get(handlers::get_all_projects_stats_by_time));
 
-    axum::serve(listener, app).await?;
+    match auth {
+        Some(auth) => {
+            router.route_layer(middleware::from_fn_with_state(auth, auth::require_api_key))
+        }
+        None => router,
+    }
+}
 
-    Ok(())
+/// 管理接口，未设置管理令牌时不注册
+fn admin_router(token: AdminToken) -> Router<AppState> {
+    Router::new()
+        .route("/admin/projects", get(handlers::list_projects))
+        .route(
+            "/admin/projects/{slug}",
+            post(handlers::create_project)
+                .put(handlers::update_project)
+                .delete(handlers::archive_project),
+        )
+        .route_layer(middleware::from_fn_with_state(token, auth::require_admin))
 }
@@ -0,0 +1,212 @@
+//! 内嵌的数据库迁移，按版本号顺序执行，已执行的版本记录在 `schema_version` 表中
+
+use std::fmt;
+
+use sqlx::{query, query_scalar, raw_sql};
+
+use crate::database::{DbPool, Dialect, with_pool};
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    sqlite: &'static str,
+    postgres: &'static str,
+}
+
+impl Migration {
+    fn sql(&self, dialect: Dialect) -> &'static str {
+        match dialect {
+            Dialect::Sqlite => self.sqlite,
+            Dialect::Postgres => self.postgres,
+        }
+    }
+}
+
+/// 所有迁移，版本号必须递增，每个迁移需要同时提供 SQLite 和 PostgreSQL 的语句
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial",
+        sqlite: include_str!("../migrations/sqlite/0001_initial.sql"),
+        postgres: include_str!("../migrations/postgres/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "projects",
+        sqlite: include_str!("../migrations/sqlite/0002_projects.sql"),
+        postgres: include_str!("../migrations/postgres/0002_projects.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "visit geo",
+        sqlite: include_str!("../migrations/sqlite/0003_visit_geo.sql"),
+        postgres: include_str!("../migrations/postgres/0003_visit_geo.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "client info",
+        sqlite: include_str!("../migrations/sqlite/0004_client_info.sql"),
+        postgres: include_str!("../migrations/postgres/0004_client_info.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "install id",
+        sqlite: include_str!("../migrations/sqlite/0005_install_id.sql"),
+        postgres: include_str!("../migrations/postgres/0005_install_id.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "privacy",
+        sqlite: include_str!("../migrations/sqlite/0006_privacy.sql"),
+        postgres: include_str!("../migrations/postgres/0006_privacy.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "retention",
+        sqlite: include_str!("../migrations/sqlite/0007_retention.sql"),
+        postgres: include_str!("../migrations/postgres/0007_retention.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "api keys",
+        sqlite: include_str!("../migrations/sqlite/0008_api_keys.sql"),
+        postgres: include_str!("../migrations/postgres/0008_api_keys.sql"),
+    },
+    Migration {
+        version: 9,
+        description: "dropped visits",
+        sqlite: include_str!("../migrations/sqlite/0009_dropped_visits.sql"),
+        postgres: include_str!("../migrations/postgres/0009_dropped_visits.sql"),
+    },
+    Migration {
+        version: 10,
+        description: "local country labels",
+        sqlite: include_str!("../migrations/sqlite/0010_local_country_labels.sql"),
+        postgres: include_str!("../migrations/postgres/0010_local_country_labels.sql"),
+    },
+    Migration {
+        version: 11,
+        description: "events",
+        sqlite: include_str!("../migrations/sqlite/0011_events.sql"),
+        postgres: include_str!("../migrations/postgres/0011_events.sql"),
+    },
+    Migration {
+        version: 12,
+        description: "visits project created_at index",
+        sqlite: include_str!("../migrations/sqlite/0012_visits_project_created_at.sql"),
+        postgres: include_str!("../migrations/postgres/0012_visits_project_created_at.sql"),
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// 数据库由更新版本的程序创建，当前程序不能安全地使用
+    DatabaseTooNew {
+        current: i64,
+        latest: i64,
+    },
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::DatabaseTooNew { current, latest } => write!(
+                f,
+                "database schema version {} is newer than the latest supported version {}",
+                current, latest
+            ),
+            MigrationError::Database(e) => write!(f, "migration failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrationError::Database(e)
+    }
+}
+
+/// 程序支持的最新版本
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// 数据库当前的版本，尚未执行过迁移时为 0
+pub async fn current_version(pool: &DbPool) -> Result<i64, MigrationError> {
+    let create_table = match pool.dialect() {
+        Dialect::Sqlite => {
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )
+            "#
+        }
+        Dialect::Postgres => {
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version BIGINT PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+            )
+            "#
+        }
+    };
+
+    let version: Option<i64> = with_pool!(pool, |p| {
+        query(create_table).execute(p).await?;
+        query_scalar("SELECT MAX(version) FROM schema_version")
+            .fetch_one(p)
+            .await
+    })?;
+
+    Ok(version.unwrap_or(0))
+}
+
+/// 执行尚未执行的迁移，返回本次执行（`dry_run` 时为将要执行）的迁移
+///
+/// 数据库版本高于程序支持的版本时返回错误
+pub async fn run(pool: &DbPool, dry_run: bool) -> Result<Vec<&'static Migration>, MigrationError> {
+    let current = current_version(pool).await?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(MigrationError::DatabaseTooNew { current, latest });
+    }
+
+    let pending: Vec<_> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+    if dry_run {
+        return Ok(pending);
+    }
+
+    for migration in &pending {
+        // 每个迁移及其版本记录在同一个事务中提交
+        let sql = migration.sql(pool.dialect());
+        with_pool!(pool, |p| {
+            let mut tx = p.begin().await?;
+            raw_sql(sql).execute(&mut *tx).await.map_err(|e| {
+                error!(
+                    "数据库迁移 {} ({}) 执行失败: {:?}",
+                    migration.version, migration.description, e
+                );
+                e
+            })?;
+            query("INSERT INTO schema_version (version, description) VALUES ($1, $2)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await
+        })?;
+
+        info!(
+            "数据库迁移 {} ({}) 执行成功",
+            migration.version, migration.description
+        );
+    }
+
+    Ok(pending)
+}
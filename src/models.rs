@@ -1,67 +1,140 @@
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use time::{Date, Duration, Month, OffsetDateTime, UtcOffset, Weekday};
+use time_tz::{Offset, OffsetResult, PrimitiveDateTimeExt, TimeZone, Tz, timezones};
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
-#[serde(rename_all = "lowercase")]
-pub enum Project {
-    Dwall,
-    Lsar,
-    UP2B,
-    Fluxy,
+/// 经过校验的项目标识，只允许小写字母、数字、`-` 和 `_`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectSlug(String);
+
+/// 项目标识格式不正确
+#[derive(Debug)]
+pub struct InvalidSlug(pub String);
+
+/// 与固定路由冲突的项目标识，如 `/track/batch`、`/stats/time`，不能用于新项目
+pub const RESERVED_SLUGS: &[&str] = &["batch", "time"];
+
+impl ProjectSlug {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_reserved(&self) -> bool {
+        RESERVED_SLUGS.contains(&self.as_str())
+    }
 }
 
-impl Project {
-    /// 项目的仓库地址
-    pub fn repository(&self) -> &'static str {
-        match self {
-            Project::Dwall => "https://github.com/dwall-rs/dwall",
-            Project::Lsar => "https://github.com/alley-rs/lsar",
-            Project::UP2B => "https://github.com/up2b/up2b",
-            Project::Fluxy => "https://github.com/alley-rs/fluxy",
+impl FromStr for ProjectSlug {
+    type Err = InvalidSlug;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let slug = s.to_ascii_lowercase();
+        let valid = !slug.is_empty()
+            && slug.len() <= 64
+            && slug
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+
+        if valid {
+            Ok(Self(slug))
+        } else {
+            Err(InvalidSlug(s.to_string()))
         }
     }
+}
 
-    pub fn icon(&self) -> &'static str {
-        match self {
-            Project::Dwall => {
-                "https://raw.githubusercontent.com/dwall-rs/dwall/refs/heads/main/src-tauri/icons/icon.ico"
-            }
-            Project::Lsar => {
-                "https://raw.githubusercontent.com/alley-rs/lsar/refs/heads/main/src-tauri/icons/icon.ico"
-            }
-            Project::UP2B => {
-                "https://raw.githubusercontent.com/up2b/up2b/refs/heads/main/src-tauri/icons/icon.ico"
-            }
-            Project::Fluxy => {
-                "https://raw.githubusercontent.com/alley-rs/fluxy/refs/heads/main/src-tauri/icons/icon.ico"
-            }
-        }
+impl TryFrom<String> for ProjectSlug {
+    type Error = InvalidSlug;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
     }
+}
 
-    pub fn description(&self) -> &'static str {
-        match self {
-            Project::Dwall => "在 Windows 中模拟 macOS 根据时间切换壁纸的程序",
-            Project::Lsar => "聚合多个平台的直播解析程序，目前支持斗鱼、虎牙、抖音、B站、Bigo",
-            Project::UP2B => "支持多个图床的图床管理程序",
-            Project::Fluxy => "轻量、快速的文件传输工具",
-        }
+impl fmt::Display for ProjectSlug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// 被统计的项目，记录在 `projects` 表中
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Project {
+    /// 项目标识，对应路由中的 `{project_name}`
+    pub slug: String,
+    /// 项目的仓库地址
+    pub repository: String,
+    pub icon: String,
+    pub description: String,
+    /// 原始访问记录的保留天数，过期的记录按天汇总，为空时永久保留
+    pub retention_days: Option<i32>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: time::OffsetDateTime,
+    /// 归档时间，已归档的项目不再接收新的访问记录
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub archived_at: Option<time::OffsetDateTime>,
+}
+
+impl Project {
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
     }
 }
 
-impl From<&String> for Project {
-    fn from(value: &String) -> Self {
-        match value.to_ascii_lowercase().as_str() {
-            "dwall" => Project::Dwall,
-            "lsar" => Project::Lsar,
-            "fluxy" => Project::Lsar,
-            "up2b" => Project::UP2B,
-            _ => panic!("无效的项目名称"),
+/// 创建项目的请求体
+#[derive(Debug, Deserialize)]
+pub struct NewProject {
+    pub repository: String,
+    pub icon: String,
+    pub description: String,
+    /// 原始访问记录的保留天数，未提供或为 0 时永久保留
+    #[serde(default)]
+    pub retention_days: Option<u16>,
+}
+
+/// 更新项目的请求体，未提供的字段保持不变
+#[derive(Debug, Deserialize)]
+pub struct ProjectUpdate {
+    pub repository: Option<String>,
+    pub icon: Option<String>,
+    pub description: Option<String>,
+    /// `false` 时取消归档
+    pub archived: Option<bool>,
+    /// 为 0 时改为永久保留
+    pub retention_days: Option<u16>,
+}
+
+/// 读取统计接口使用的 API key，不包含 key 本身
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    /// 便于识别用途的名称
+    pub name: String,
+    /// 只能读取该项目的统计，为空时可以读取所有项目
+    pub project_name: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: time::OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub revoked_at: Option<time::OffsetDateTime>,
+}
+
+impl ApiKey {
+    /// 是否可以读取 `project` 的统计，`None` 表示所有项目的汇总
+    pub fn allows(&self, project: Option<&ProjectSlug>) -> bool {
+        match (&self.project_name, project) {
+            (None, _) => true,
+            (Some(scope), Some(project)) => scope == project.as_str(),
+            (Some(_), None) => false,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
+/// 以枚举名（如`Windows`、`MacOS`）保存为文本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text")]
 pub enum Platform {
     Windows,
     MacOS,
@@ -71,20 +144,25 @@ pub enum Platform {
     Unknown,
 }
 
+/// 公开的访问记录，不包含 IP 地址
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Visit {
     pub id: i64,
-    pub project_name: Project,
-    pub ip_address: String,
+    pub project_name: String,
     pub platform: Platform,
     pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: time::OffsetDateTime,
+    pub app_version: Option<String>,
+    pub os_version: Option<String>,
+    pub arch: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProjectStats {
-    pub project_name: Project,
+    pub project_name: String,
     pub repository: String,
     pub icon: String,
     pub description: String,
@@ -93,36 +171,86 @@ pub struct ProjectStats {
 }
 
 impl ProjectStats {
-    pub fn new(project: Project, total_visits: u64, unique_visitors: u64) -> Self {
+    /// 计数来自 `COUNT()`，数据库统一以 64 位有符号整数返回
+    pub fn new(project: &Project, total_visits: i64, unique_visitors: i64) -> Self {
         Self {
-            repository: project.repository().to_string(),
-            icon: project.icon().to_string(),
-            description: project.description().to_string(),
-            project_name: project,
-            total_visits,
-            unique_visitors,
+            project_name: project.slug.clone(),
+            repository: project.repository.clone(),
+            icon: project.icon.clone(),
+            description: project.description.clone(),
+            total_visits: total_visits as u64,
+            unique_visitors: unique_visitors as u64,
         }
     }
+}
 
-    pub fn new_unchecked(project: String, total_visits: u64, unique_visitors: u64) -> Self {
-        let project = Project::from(&project);
-        Self {
-            repository: project.repository().to_string(),
-            icon: project.icon().to_string(),
-            description: project.description().to_string(),
-            project_name: project,
-            total_visits,
-            unique_visitors,
-        }
-    }
+/// 汇总未登记项目时使用的名称
+pub const ORPHANED_BUCKET: &str = "orphaned";
+
+/// 统计所有项目时，对项目名称未登记的访问记录的处理方式
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanPolicy {
+    /// 忽略
+    #[default]
+    Skip,
+    /// 按项目名称分别列出
+    Report,
+    /// 汇总为一项 `orphaned`
+    Bucket,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OrphanParams {
+    #[serde(default)]
+    pub orphans: OrphanPolicy,
+}
+
+/// 未登记项目的访问统计
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct OrphanedStats {
+    pub project_name: String,
+    pub total_visits: i64,
+    pub unique_visitors: i64,
+}
+
+/// 所有项目的统计
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AllProjectsStats {
+    pub projects: Vec<ProjectStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orphaned: Option<Vec<OrphanedStats>>,
 }
 
+/// 按国家统计的访问次数，`country` 为 ISO 3166-1 国家代码，
+/// 非公网地址为 `Loopback`、`Private` 或 `Reserved`，查不到时为 `Unknown`
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct CountryStats {
     pub country: Option<String>,
     pub visit_count: i64,
 }
 
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct PlatformStats {
+    pub platform: Platform,
+    pub visit_count: i64,
+}
+
+/// 单个平台的访问及其按国家的分布
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlatformCountryStats {
+    pub platform: Platform,
+    pub visit_count: i64,
+    pub countries: Vec<CountryStats>,
+}
+
+/// 项目按平台和国家的交叉统计
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlatformBreakdown {
+    pub project_name: String,
+    pub platforms: Vec<PlatformCountryStats>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TrackResponse {
     pub success: bool,
@@ -131,40 +259,880 @@ pub struct TrackResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProjectDetailedStats {
-    pub project_name: Project,
+    pub project_name: String,
     pub repository: String,
     pub icon: String,
     pub description: String,
     pub total_visits: u64,
     pub unique_visitors: u64,
     pub country_stats: Vec<CountryStats>,
+    pub platform_stats: Vec<PlatformStats>,
     pub recent_visits: Vec<Visit>,
+    pub dropped_visits: DroppedVisits,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(untagged)]
+/// 未计入访问记录的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum DropReason {
+    /// 超出速率限制
+    RateLimited,
+    /// 去重时间内已记录过同一访客
+    Duplicate,
+    /// 自定义事件超出速率限制，不计入 [`DroppedVisits`]
+    EventRateLimited,
+}
+
+/// 项目因限流或去重未计入的访问次数
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DroppedVisits {
+    pub rate_limited: u64,
+    pub duplicate: u64,
+}
+
+/// 项目在一段时间内的统计
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectRangeStats {
+    pub project_name: String,
+    pub repository: String,
+    pub icon: String,
+    pub description: String,
+    pub total_visits: u64,
+    pub unique_visitors: u64,
+    pub country_stats: Vec<CountryStats>,
+    pub platform_stats: Vec<PlatformStats>,
+}
+
+/// 左闭右开的时间范围
+pub type TimeRange = (OffsetDateTime, OffsetDateTime);
+
+/// 按时间查询的条件，每种条件都对应一段左闭右开的时间范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimeQuery {
-    Date {
-        date: String,
-    },
-    Month {
-        month: String,
-    },
-    Year {
-        year: String,
-    },
-    Range {
-        start_date: String,
-        end_date: String,
-    },
+    /// `YYYY-MM-DD`
+    Date(Date),
+    /// ISO 周，`YYYY-Www`
+    Week { year: i32, week: u8 },
+    /// `YYYY-MM`
+    Month { year: i32, month: Month },
+    /// 季度，`YYYY-Qn`
+    Quarter { year: i32, quarter: u8 },
+    /// `YYYY`
+    Year(i32),
+    /// 日期范围，包含开始和结束日期
+    Range { start: Date, end: Date },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl TimeQuery {
+    /// 查询覆盖的日期，左闭右开
+    ///
+    /// 解析时年份限制在 9999 以前，结束日期总是有效的
+    pub fn dates(&self) -> (Date, Date) {
+        let first_of = |year: i32, month: Month| {
+            Date::from_calendar_date(year, month, 1).expect("every month has a first day")
+        };
+
+        match *self {
+            TimeQuery::Date(date) => (date, date + Duration::DAY),
+            TimeQuery::Week { year, week } => {
+                let start = Date::from_iso_week_date(year, week, Weekday::Monday)
+                    .expect("week is validated when parsed");
+                (start, start + Duration::WEEK)
+            }
+            TimeQuery::Month { year, month } => {
+                let end = match month {
+                    Month::December => first_of(year + 1, Month::January),
+                    month => first_of(year, month.next()),
+                };
+                (first_of(year, month), end)
+            }
+            TimeQuery::Quarter { year, quarter } => {
+                let start = first_of(year, Month::January.nth_next((quarter - 1) * 3));
+                let end = match quarter {
+                    4 => first_of(year + 1, Month::January),
+                    _ => first_of(year, Month::January.nth_next(quarter * 3)),
+                };
+                (start, end)
+            }
+            TimeQuery::Year(year) => (
+                first_of(year, Month::January),
+                first_of(year + 1, Month::January),
+            ),
+            TimeQuery::Range { start, end } => (start, end + Duration::DAY),
+        }
+    }
+
+    /// 查询覆盖的时间范围，日期按 `tz` 划分
+    pub fn bounds(&self, tz: StatsTimeZone) -> Result<TimeRange, InvalidTimeQuery> {
+        let (start, end) = self.dates();
+        Ok((tz.start_of_day(start)?, tz.start_of_day(end)?))
+    }
+}
+
+/// 统计查询支持的年份
+pub const SUPPORTED_YEARS: RangeInclusive<i32> = 1..=9998;
+
+/// 统计时划分日期使用的时区，默认为 UTC
+#[derive(Debug, Clone, Copy)]
+pub enum StatsTimeZone {
+    /// 固定偏移，如 `+08:00`
+    Fixed(UtcOffset),
+    /// IANA 时区，如 `Asia/Shanghai`
+    Named(&'static Tz),
+}
+
+impl Default for StatsTimeZone {
+    fn default() -> Self {
+        StatsTimeZone::Fixed(UtcOffset::UTC)
+    }
+}
+
+impl StatsTimeZone {
+    /// 解析查询参数中的 `tz`，未提供时使用 UTC
+    pub fn from_param(tz: Option<&str>) -> Result<Self, InvalidTimeQuery> {
+        tz.map_or(Ok(Self::default()), str::parse)
+    }
+
+    /// `date` 在该时区开始的时刻（UTC）
+    ///
+    /// 零点处于夏令时跳过的时段时，一天从切换的时刻开始；零点重复出现时取较早的一次
+    ///
+    /// 只支持 [`SUPPORTED_YEARS`] 内的日期，保证换算时区后的时刻仍可以表示并写入数据库
+    pub fn start_of_day(self, date: Date) -> Result<OffsetDateTime, InvalidTimeQuery> {
+        let out_of_range = || {
+            InvalidTimeQuery(format!(
+                "date `{}` is out of the supported range {}-{}",
+                date,
+                SUPPORTED_YEARS.start(),
+                SUPPORTED_YEARS.end()
+            ))
+        };
+        if !SUPPORTED_YEARS.contains(&date.year()) {
+            return Err(out_of_range());
+        }
+        let midnight = date.midnight();
+        let start = match self {
+            StatsTimeZone::Fixed(offset) => midnight.assume_offset(offset),
+            StatsTimeZone::Named(tz) => match midnight.assume_timezone(tz) {
+                OffsetResult::Some(start) => start,
+                OffsetResult::Ambiguous(a, b) => a.min(b),
+                OffsetResult::None => {
+                    // 按切换前的偏移换算即为切换的时刻
+                    let day_before = midnight
+                        .assume_utc()
+                        .checked_sub(Duration::DAY)
+                        .ok_or_else(out_of_range)?;
+                    let before = tz.get_offset_utc(&day_before);
+                    midnight.assume_offset(before.to_utc())
+                }
+            },
+        };
+
+        start
+            .checked_to_offset(UtcOffset::UTC)
+            .ok_or_else(out_of_range)
+    }
+}
+
+impl FromStr for StatsTimeZone {
+    type Err = InvalidTimeQuery;
+
+    /// 接受 `UTC`、`Z`、`±HH`、`±HHMM`、`±HH:MM` 或 IANA 时区名称
+    ///
+    /// 查询字符串中未编码的 `+` 会被解码为空格，因此开头的空格也视为 `+`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(Self::default());
+        }
+
+        let sign = match s.as_bytes().first() {
+            Some(b'+' | b' ') => Some(1),
+            Some(b'-') => Some(-1),
+            _ => None,
+        };
+        if let Some(sign) = sign {
+            let invalid = || {
+                InvalidTimeQuery(format!(
+                    "time zone offset `{}{}` is invalid, expected ±HH:MM between -14:00 and +14:00",
+                    if sign > 0 { '+' } else { '-' },
+                    &s[1..]
+                ))
+            };
+            // 偏移中可能有非 ASCII 字符，只在字符边界处切分
+            let offset = &s[1..];
+            let (hours, minutes) = match offset.split_once(':') {
+                Some((hours, minutes)) => (hours, minutes),
+                None if offset.is_char_boundary(2) => offset.split_at(2),
+                None => return Err(invalid()),
+            };
+            let hours = parse_digits(hours, 2);
+            let minutes = match minutes {
+                "" if !offset.contains(':') => Some(0),
+                minutes => parse_digits(minutes, 2),
+            };
+            let (hours, minutes) = hours
+                .zip(minutes)
+                .filter(|&(h, m)| m < 60 && h * 60 + m <= 14 * 60)
+                .ok_or_else(invalid)?;
+            return UtcOffset::from_hms(sign * hours as i8, sign * minutes as i8, 0)
+                .map(StatsTimeZone::Fixed)
+                .map_err(|_| invalid());
+        }
+
+        timezones::get_by_name(s)
+            .map(StatsTimeZone::Named)
+            .ok_or_else(|| InvalidTimeQuery(format!("unknown time zone `{}`", s)))
+    }
+}
+
+/// 时间查询参数格式不正确
+#[derive(Debug)]
+pub struct InvalidTimeQuery(pub String);
+
+/// 原始的时间查询参数，通过 [`TimeQueryParams::parse`] 转换为 [`TimeQuery`]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct TimeQueryParams {
-    pub time: Option<TimeQuery>,
+    pub date: Option<String>,
+    pub week: Option<String>,
+    pub month: Option<String>,
+    pub quarter: Option<String>,
+    pub year: Option<String>,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    /// 划分日期使用的时区
+    pub tz: Option<String>,
+}
+
+impl TimeQueryParams {
+    /// 未提供任何时间条件时返回 `None`，只能提供一种条件
+    pub fn parse(&self) -> Result<Option<TimeQuery>, InvalidTimeQuery> {
+        let range = match (&self.start_date, &self.end_date) {
+            (Some(start), Some(end)) => Some((start, end)),
+            (None, None) => None,
+            _ => {
+                return Err(InvalidTimeQuery(
+                    "start_date and end_date must be provided together".to_string(),
+                ));
+            }
+        };
+
+        let provided = [
+            &self.date,
+            &self.week,
+            &self.month,
+            &self.quarter,
+            &self.year,
+        ]
+        .iter()
+        .filter(|v| v.is_some())
+        .count()
+            + usize::from(range.is_some());
+        if provided > 1 {
+            return Err(InvalidTimeQuery(
+                "only one of date, week, month, quarter, year or start_date/end_date may be provided"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(date) = &self.date {
+            return parse_date("date", date).map(|date| Some(TimeQuery::Date(date)));
+        }
+        if let Some(week) = &self.week {
+            return parse_week(week).map(Some);
+        }
+        if let Some(month) = &self.month {
+            return parse_month(month).map(Some);
+        }
+        if let Some(quarter) = &self.quarter {
+            return parse_quarter(quarter).map(Some);
+        }
+        if let Some(year) = &self.year {
+            return parse_year(year)
+                .map(|year| Some(TimeQuery::Year(year)))
+                .ok_or_else(|| invalid_format("year", year, "YYYY"));
+        }
+        if let Some((start, end)) = range {
+            let start = parse_date("start_date", start)?;
+            let end = parse_date("end_date", end)?;
+            if start > end {
+                return Err(InvalidTimeQuery(
+                    "start_date must not be after end_date".to_string(),
+                ));
+            }
+            return Ok(Some(TimeQuery::Range { start, end }));
+        }
+
+        Ok(None)
+    }
+}
+
+fn invalid_format(name: &str, value: &str, format: &str) -> InvalidTimeQuery {
+    InvalidTimeQuery(format!(
+        "{} `{}` is invalid, expected {}",
+        name, value, format
+    ))
+}
+
+/// 解析固定位数的十进制数
+fn parse_digits(s: &str, digits: usize) -> Option<u16> {
+    if s.len() != digits || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// 四位数的年份，不接受 9999 年，保证时间范围的结束日期有效
+fn parse_year(s: &str) -> Option<i32> {
+    parse_digits(s, 4)
+        .filter(|&year| year < 9999)
+        .map(i32::from)
+}
+
+/// 解析 `YYYY<separator><suffix>`，返回年份和 `suffix` 之后的部分
+fn split_year<'a>(s: &'a str, separator: &str) -> Option<(i32, &'a str)> {
+    let (year, rest) = s.split_once('-')?;
+    Some((parse_year(year)?, rest.strip_prefix(separator)?))
+}
+
+fn parse_date(name: &str, s: &str) -> Result<Date, InvalidTimeQuery> {
+    let invalid = || invalid_format(name, s, "YYYY-MM-DD");
+    let (year, rest) = split_year(s, "").ok_or_else(invalid)?;
+    let (month, day) = rest.split_once('-').ok_or_else(invalid)?;
+    let month = parse_digits(month, 2).ok_or_else(invalid)?;
+    let day = parse_digits(day, 2).ok_or_else(invalid)?;
+
+    let month = u8::try_from(month)
+        .ok()
+        .and_then(|m| Month::try_from(m).ok())
+        .ok_or_else(|| InvalidTimeQuery(format!("{} `{}` has no month {}", name, s, month)))?;
+    u8::try_from(day)
+        .ok()
+        .and_then(|d| Date::from_calendar_date(year, month, d).ok())
+        .ok_or_else(|| InvalidTimeQuery(format!("{} `{}` does not exist", name, s)))
+}
+
+fn parse_week(s: &str) -> Result<TimeQuery, InvalidTimeQuery> {
+    let (year, week) = split_year(s, "W").ok_or_else(|| invalid_format("week", s, "YYYY-Www"))?;
+    let week = parse_digits(week, 2).ok_or_else(|| invalid_format("week", s, "YYYY-Www"))?;
+
+    let weeks = time::util::weeks_in_year(year);
+    match u8::try_from(week) {
+        Ok(week) if (1..=weeks).contains(&week) => Ok(TimeQuery::Week { year, week }),
+        _ => Err(InvalidTimeQuery(format!(
+            "week `{}` is out of range, {} has {} ISO weeks",
+            s, year, weeks
+        ))),
+    }
+}
+
+fn parse_month(s: &str) -> Result<TimeQuery, InvalidTimeQuery> {
+    let invalid = || invalid_format("month", s, "YYYY-MM");
+    let (year, month) = split_year(s, "").ok_or_else(invalid)?;
+    let month = parse_digits(month, 2).ok_or_else(invalid)?;
+
+    u8::try_from(month)
+        .ok()
+        .and_then(|m| Month::try_from(m).ok())
+        .map(|month| TimeQuery::Month { year, month })
+        .ok_or_else(|| InvalidTimeQuery(format!("month `{}` has no month {}", s, month)))
+}
+
+fn parse_quarter(s: &str) -> Result<TimeQuery, InvalidTimeQuery> {
+    let invalid = || invalid_format("quarter", s, "YYYY-Qn");
+    let (year, quarter) = split_year(s, "Q").ok_or_else(invalid)?;
+
+    match parse_digits(quarter, 1) {
+        Some(quarter @ 1..=4) => Ok(TimeQuery::Quarter {
+            year,
+            quarter: quarter as u8,
+        }),
+        Some(_) => Err(InvalidTimeQuery(format!(
+            "quarter `{}` is out of range, expected Q1 to Q4",
+            s
+        ))),
+        None => Err(invalid()),
+    }
+}
+
+/// 时间序列最多包含的时间段数量
+pub const MAX_SERIES_BUCKETS: usize = 1000;
+
+/// 时间序列中每个时间段的长度
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    #[default]
+    Day,
+    /// ISO 周，从周一开始
+    Week,
+    Month,
+}
+
+impl Interval {
+    /// `date` 所在时间段的第一天
+    fn bucket_start(self, date: Date) -> Date {
+        match self {
+            Interval::Day => date,
+            Interval::Week => {
+                date - Duration::days(date.weekday().number_days_from_monday().into())
+            }
+            Interval::Month => date.replace_day(1).expect("every month has a first day"),
+        }
+    }
+
+    /// 下一个时间段的第一天
+    fn next_bucket(self, start: Date) -> Option<Date> {
+        match self {
+            Interval::Day => start.next_day(),
+            Interval::Week => start.checked_add(Duration::weeks(1)),
+            Interval::Month => {
+                let (year, month) = match start.month() {
+                    Month::December => (start.year() + 1, Month::January),
+                    month => (start.year(), month.next()),
+                };
+                Date::from_calendar_date(year, month, 1).ok()
+            }
+        }
+    }
+
+    /// 覆盖 `start` 至 `end`（含）的所有时间段的边界，`start` 和 `end` 会扩展到完整的时间段
+    ///
+    /// 返回的边界比时间段多一个，时间段数量超过 `max` 时返回 `None`
+    pub fn boundaries(self, start: Date, end: Date, max: usize) -> Option<Vec<Date>> {
+        let mut boundaries = vec![self.bucket_start(start)];
+        while let Some(&last) = boundaries.last()
+            && last <= end
+        {
+            if boundaries.len() > max {
+                return None;
+            }
+            boundaries.push(self.next_bucket(last)?);
+        }
+
+        Some(boundaries)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub struct PlatformParams {
+pub struct SeriesParams {
+    #[serde(default)]
+    pub interval: Interval,
+    /// 开始日期（含）
+    pub start: Date,
+    /// 结束日期（含）
+    pub end: Date,
+    /// 划分时间段使用的时区，默认为 UTC
+    pub tz: Option<String>,
+}
+
+/// 时间序列中的一个时间段
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeriesBucket {
+    /// 时间段的第一天
+    pub start: Date,
+    pub total_visits: u64,
+    pub unique_visitors: u64,
+}
+
+/// 项目按时间段的访问统计，按时间顺序排列，没有访问的时间段计数为 0
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectSeries {
+    pub project_name: String,
+    pub interval: Interval,
+    pub buckets: Vec<SeriesBucket>,
+}
+
+/// 上报访问时提交的客户端信息，可以放在查询参数或 JSON 请求体中
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TrackParams {
+    pub platform: Option<Platform>,
+    pub app_version: Option<String>,
+    pub os_version: Option<String>,
+    pub arch: Option<String>,
+    /// 客户端每次安装时随机生成的标识，如 UUID
+    pub install_id: Option<String>,
+}
+
+impl TrackParams {
+    /// 合并查询参数和请求体，两者都提供的字段以请求体为准
+    pub fn merge(self, body: TrackParams) -> Self {
+        Self {
+            platform: body.platform.or(self.platform),
+            app_version: body.app_version.or(self.app_version),
+            os_version: body.os_version.or(self.os_version),
+            arch: body.arch.or(self.arch),
+            install_id: body.install_id.or(self.install_id),
+        }
+    }
+}
+
+/// 经过校验的访问记录
+#[derive(Debug)]
+pub struct NewVisit {
     pub platform: Platform,
+    pub app_version: Option<String>,
+    pub os_version: Option<String>,
+    /// 统一为小写，如 `x86_64`、`aarch64`
+    pub arch: Option<String>,
+    /// 安装标识的 SHA-256 哈希（十六进制）
+    pub install_id: Option<String>,
+    /// 访问发生的时间，为空时使用写入时的服务器时间
+    pub created_at: Option<OffsetDateTime>,
+}
+
+/// `/track/batch` 中的一条访问
+#[derive(Debug, Deserialize)]
+pub struct BatchEvent {
+    /// 项目标识
+    pub project: String,
+    /// 访问发生的时间（RFC 3339），未提供时使用服务器时间
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub timestamp: Option<OffsetDateTime>,
+    #[serde(flatten)]
+    pub params: TrackParams,
+}
+
+/// 批量上报中单条访问的处理结果
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    Accepted,
+    /// 去重时间内已记录过同一访客
+    Duplicate,
+    Rejected,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchItemResult {
+    pub status: BatchItemStatus,
+    /// 被拒绝的原因，与错误响应中的 `code`、`message` 相同
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// `/track/batch` 的响应，`results` 与请求中的访问一一对应
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchTrackResponse {
+    pub success: bool,
+    /// 写入的访问数量
+    pub accepted: usize,
+    pub results: Vec<BatchItemResult>,
+}
+
+/// 单个应用版本的访问统计，`app_version` 为空表示客户端未上报版本
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct VersionStats {
+    pub app_version: Option<String>,
+    pub visit_count: i64,
+    pub unique_visitors: i64,
+}
+
+/// 项目各应用版本的使用情况
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionAdoption {
+    pub project_name: String,
+    pub versions: Vec<VersionStats>,
+}
+
+/// 上报自定义事件的请求体
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventParams {
+    /// 事件名称，如 `wallpaper_changed`
+    pub name: String,
+    /// 事件属性，值只能是字符串、数字或布尔值，`null` 视为未提供
+    #[serde(default)]
+    pub properties: serde_json::Map<String, serde_json::Value>,
+}
+
+/// 经过校验的自定义事件，属性值已转换为文本
+#[derive(Debug)]
+pub struct NewEvent {
+    pub name: String,
+    pub properties: Vec<(String, String)>,
+}
+
+/// 单个事件名称的发生次数
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct EventCount {
+    pub name: String,
+    pub count: i64,
+}
+
+/// 项目各自定义事件的发生次数，按次数降序排列
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventOverview {
+    pub project_name: String,
+    pub events: Vec<EventCount>,
+    /// 因超出速率限制未记录的事件数量，不受时间条件限制
+    pub rate_limited: u64,
+}
+
+/// 属性的一个取值及其出现次数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PropertyValueCount {
+    pub value: String,
+    pub count: i64,
+}
+
+/// 单个属性出现次数最多的取值，按次数降序排列
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PropertyStats {
+    pub key: String,
+    pub values: Vec<PropertyValueCount>,
+}
+
+/// 单个自定义事件的发生次数及各属性出现次数最多的取值
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventDetail {
+    pub project_name: String,
+    pub name: String,
+    pub count: u64,
+    pub properties: Vec<PropertyStats>,
+}
+
+/// 事件时间序列中的一个时间段
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventSeriesBucket {
+    /// 时间段的第一天
+    pub start: Date,
+    pub count: u64,
+}
+
+/// 单个自定义事件按时间段的发生次数，按时间顺序排列，没有发生的时间段计数为 0
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventSeries {
+    pub project_name: String,
+    pub name: String,
+    pub interval: Interval,
+    pub buckets: Vec<EventSeriesBucket>,
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{PrimitiveDateTime, Time};
+
+    use super::*;
+
+    fn date(year: i32, month: u8, day: u8) -> Date {
+        Date::from_calendar_date(year, Month::try_from(month).unwrap(), day).unwrap()
+    }
+
+    fn utc(year: i32, month: u8, day: u8, hour: u8) -> OffsetDateTime {
+        PrimitiveDateTime::new(date(year, month, day), Time::from_hms(hour, 0, 0).unwrap())
+            .assume_utc()
+    }
+
+    fn offset(tz: &str) -> Option<UtcOffset> {
+        match tz.parse::<StatsTimeZone>() {
+            Ok(StatsTimeZone::Fixed(offset)) => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// 按查询字符串中的参数名构造并解析时间查询
+    fn query(params: &[(&str, &str)]) -> Result<Option<TimeQuery>, InvalidTimeQuery> {
+        let params: serde_json::Map<_, _> = params
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.into()))
+            .collect();
+        serde_json::from_value::<TimeQueryParams>(params.into())
+            .unwrap()
+            .parse()
+    }
+
+    fn dates(params: &[(&str, &str)]) -> (Date, Date) {
+        query(params).unwrap().unwrap().dates()
+    }
+
+    #[test]
+    fn parses_time_queries() {
+        assert!(query(&[]).unwrap().is_none());
+        for (params, expected) in [
+            (
+                &[("date", "2024-02-29")][..],
+                (date(2024, 2, 29), date(2024, 3, 1)),
+            ),
+            // 2021-W01 从 2021-01-04 开始，2020 年有 53 周
+            (
+                &[("week", "2021-W01")],
+                (date(2021, 1, 4), date(2021, 1, 11)),
+            ),
+            (
+                &[("week", "2020-W53")],
+                (date(2020, 12, 28), date(2021, 1, 4)),
+            ),
+            (
+                &[("month", "2024-12")],
+                (date(2024, 12, 1), date(2025, 1, 1)),
+            ),
+            (
+                &[("quarter", "2024-Q2")],
+                (date(2024, 4, 1), date(2024, 7, 1)),
+            ),
+            (
+                &[("quarter", "2024-Q4")],
+                (date(2024, 10, 1), date(2025, 1, 1)),
+            ),
+            (&[("year", "9998")], (date(9998, 1, 1), date(9999, 1, 1))),
+            (
+                &[("start_date", "2024-01-30"), ("end_date", "2024-02-02")],
+                (date(2024, 1, 30), date(2024, 2, 3)),
+            ),
+        ] {
+            assert_eq!(dates(params), expected, "{params:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_time_queries() {
+        for param in [
+            ("date", "2024-1-01"),
+            ("date", "2024/01/01"),
+            ("date", "+2024-01-01"),
+            ("date", "2024-01-0é"),
+            ("week", "2024-W1"),
+            ("week", "2024W01"),
+            ("week", "2024-w01"),
+            ("month", "2024-1"),
+            ("month", "2024-001"),
+            ("quarter", "2024-Q"),
+            ("quarter", "2024-Q01"),
+            ("year", "24"),
+            ("year", "-2024"),
+            ("year", "２０２４"),
+        ] {
+            assert!(query(&[param]).is_err(), "{param:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_time_queries() {
+        for params in [
+            &[("date", "2023-02-29")][..],
+            &[("date", "2024-13-01")],
+            &[("week", "2021-W53")],
+            &[("week", "2020-W00")],
+            &[("month", "2024-00")],
+            &[("month", "2024-13")],
+            &[("quarter", "2024-Q5")],
+            &[("quarter", "2024-Q0")],
+            &[("year", "9999")],
+            &[("start_date", "2024-02-02"), ("end_date", "2024-02-01")],
+        ] {
+            assert!(query(params).is_err(), "{params:?}");
+        }
+
+        // 0 年可以解析，但不在支持的范围内
+        let year_zero = query(&[("date", "0000-01-01")]).unwrap().unwrap();
+        assert!(year_zero.bounds(StatsTimeZone::default()).is_err());
+    }
+
+    #[test]
+    fn rejects_combined_time_queries() {
+        assert!(query(&[("date", "2024-01-01"), ("month", "2024-01")]).is_err());
+        assert!(query(&[("start_date", "2024-01-01")]).is_err());
+    }
+
+    #[test]
+    fn parses_fixed_offsets() {
+        for (tz, seconds) in [
+            ("UTC", 0),
+            ("Z", 0),
+            ("+08", 8 * 3600),
+            (" 08:00", 8 * 3600),
+            ("-0530", -(5 * 3600 + 30 * 60)),
+            ("+05:45", 5 * 3600 + 45 * 60),
+            ("+14:00", 14 * 3600),
+            ("-14", -14 * 3600),
+        ] {
+            assert_eq!(
+                offset(tz).map(UtcOffset::whole_seconds),
+                Some(seconds),
+                "{tz}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_offsets() {
+        for tz in [
+            "+",
+            "+8",
+            "+123",
+            "+12345",
+            "+12:",
+            "+1:200",
+            "+12:3",
+            "+ab",
+            "+14:01",
+            "-15",
+            "+08:60",
+            "+1é1",
+            "+é1",
+            "+1é",
+            "-1\u{300}00",
+            "+08:é",
+        ] {
+            assert!(tz.parse::<StatsTimeZone>().is_err(), "{tz}");
+        }
+    }
+
+    #[test]
+    fn parses_named_zones() {
+        assert!(matches!(
+            "Asia/Shanghai".parse(),
+            Ok(StatsTimeZone::Named(_))
+        ));
+        assert!("Mars/Olympus".parse::<StatsTimeZone>().is_err());
+        assert!("".parse::<StatsTimeZone>().is_err());
+    }
+
+    #[test]
+    fn day_starts_at_local_midnight() {
+        let tz: StatsTimeZone = "Asia/Shanghai".parse().unwrap();
+        assert_eq!(
+            tz.start_of_day(date(2025, 1, 1)).unwrap(),
+            utc(2024, 12, 31, 16)
+        );
+        let tz: StatsTimeZone = "-05:00".parse().unwrap();
+        assert_eq!(
+            tz.start_of_day(date(2025, 1, 1)).unwrap(),
+            utc(2025, 1, 1, 5)
+        );
+    }
+
+    #[test]
+    fn day_skipping_midnight_starts_at_transition() {
+        // 2018-11-04 圣保罗从 00:00（-03）直接跳到 01:00（-02）
+        let tz: StatsTimeZone = "America/Sao_Paulo".parse().unwrap();
+        assert_eq!(
+            tz.start_of_day(date(2018, 11, 4)).unwrap(),
+            utc(2018, 11, 4, 3)
+        );
+        assert_eq!(
+            tz.start_of_day(date(2018, 11, 5)).unwrap(),
+            utc(2018, 11, 5, 2)
+        );
+    }
+
+    #[test]
+    fn day_repeating_midnight_starts_at_first_occurrence() {
+        // 2018-10-28 哈瓦那 01:00（-04）回拨到 00:00（-05），零点出现两次
+        let tz: StatsTimeZone = "America/Havana".parse().unwrap();
+        assert_eq!(
+            tz.start_of_day(date(2018, 10, 28)).unwrap(),
+            utc(2018, 10, 28, 4)
+        );
+    }
+
+    #[test]
+    fn day_outside_supported_years_is_rejected() {
+        let tz: StatsTimeZone = "+14:00".parse().unwrap();
+        assert!(tz.start_of_day(date(9999, 1, 1)).is_err());
+        assert!(tz.start_of_day(date(0, 12, 31)).is_err());
+        assert!(tz.start_of_day(date(1, 1, 1)).is_ok());
+    }
 }
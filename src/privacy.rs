@@ -0,0 +1,243 @@
+//! 访问记录中客户端标识的匿名化
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use time::{Date, OffsetDateTime};
+use tokio::sync::Mutex;
+
+use crate::database::{self, DbPool};
+
+/// 保存 IP 地址的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpMode {
+    /// 保存原始地址
+    #[default]
+    Raw,
+    /// 保存加盐哈希，盐每天（UTC）更换，前一天的盐随即删除
+    Hash,
+    /// IPv4 保留前 24 位，IPv6 保留前 48 位
+    Truncate,
+}
+
+impl FromStr for IpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(IpMode::Raw),
+            "hash" => Ok(IpMode::Hash),
+            "truncate" => Ok(IpMode::Truncate),
+            _ => Err(format!(
+                "unknown ip mode `{}`, expected raw, hash or truncate",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IpMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IpMode::Raw => "raw",
+            IpMode::Hash => "hash",
+            IpMode::Truncate => "truncate",
+        })
+    }
+}
+
+/// 访问记录中保存的 IP 地址
+#[derive(Debug)]
+pub struct StoredIp {
+    /// 写入 `visits.ip_address` 的值
+    pub ip_address: String,
+    /// 补充地理位置前暂存的原始地址，保存原始地址时为 `None`
+    pub pending_ip: Option<String>,
+}
+
+/// 按配置的方式匿名化 IP 地址，克隆后共享当天的盐
+#[derive(Clone)]
+pub struct IpAnonymizer {
+    mode: IpMode,
+    salt: Arc<Mutex<Option<(Date, String)>>>,
+}
+
+impl IpAnonymizer {
+    pub fn new(mode: IpMode) -> Self {
+        Self {
+            mode,
+            salt: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub async fn anonymize(&self, pool: &DbPool, ip: &str) -> Result<StoredIp, sqlx::Error> {
+        if self.mode == IpMode::Raw {
+            return Ok(StoredIp {
+                ip_address: ip.to_string(),
+                pending_ip: None,
+            });
+        }
+
+        // 无法解析的值来自请求头，原样保存可能泄露任意内容
+        let Ok(addr) = ip.parse::<IpAddr>().map(|addr| addr.to_canonical()) else {
+            return Ok(StoredIp {
+                ip_address: "unknown".to_string(),
+                pending_ip: None,
+            });
+        };
+
+        let ip_address = match self.mode {
+            IpMode::Raw => unreachable!(),
+            IpMode::Hash => {
+                let salt = self.daily_salt(pool).await?;
+                sha256_hex(format!("{}{}", salt, addr).as_bytes())
+            }
+            IpMode::Truncate => truncate(addr).to_string(),
+        };
+
+        Ok(StoredIp {
+            ip_address,
+            pending_ip: Some(addr.to_string()),
+        })
+    }
+
+    /// 当天的盐，首次使用时从数据库读取或生成
+    ///
+    /// 多个实例共用一个数据库时，以先写入数据库的盐为准
+    async fn daily_salt(&self, pool: &DbPool) -> Result<String, sqlx::Error> {
+        let today = OffsetDateTime::now_utc().date();
+        let mut cached = self.salt.lock().await;
+        if let Some((day, salt)) = &*cached
+            && *day == today
+        {
+            return Ok(salt.clone());
+        }
+
+        let salt = database::get_or_create_ip_salt(pool, today, &random_hex(32)).await?;
+        *cached = Some((today, salt.clone()));
+
+        Ok(salt)
+    }
+}
+
+/// IPv4 保留前 24 位，IPv6 保留前 48 位
+fn truncate(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(v6) => {
+            let [a, b, c, ..] = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+/// 安装标识只保存哈希值
+pub fn hash_install_id(install_id: &str) -> String {
+    sha256_hex(install_id.as_bytes())
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// `len` 字节的随机数，以十六进制表示
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes).expect("system random number generator is unavailable");
+    hex(&bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncated(ip: &str) -> String {
+        truncate(ip.parse().unwrap()).to_string()
+    }
+
+    #[test]
+    fn truncates_to_network_prefix() {
+        assert_eq!(truncated("203.0.113.77"), "203.0.113.0");
+        assert_eq!(truncated("203.0.113.0"), "203.0.113.0");
+        assert_eq!(truncated("2001:db8:abcd:12::1"), "2001:db8:abcd::");
+        assert_eq!(truncated("2001:db8:abcd:ffff:ffff::"), "2001:db8:abcd::");
+    }
+
+    #[test]
+    fn parses_ip_modes() {
+        for mode in [IpMode::Raw, IpMode::Hash, IpMode::Truncate] {
+            assert_eq!(mode.to_string().parse::<IpMode>(), Ok(mode));
+        }
+        assert!("Hash".parse::<IpMode>().is_err());
+    }
+
+    async fn pool() -> DbPool {
+        database::init_database("sqlite::memory:")
+            .await
+            .expect("in-memory sqlite should initialize")
+    }
+
+    #[tokio::test]
+    async fn hashes_are_stable_within_a_day() {
+        let pool = pool().await;
+        let anonymizer = IpAnonymizer::new(IpMode::Hash);
+        let first = anonymizer.anonymize(&pool, "203.0.113.77").await.unwrap();
+        let again = anonymizer.anonymize(&pool, "203.0.113.77").await.unwrap();
+        let other = anonymizer.anonymize(&pool, "203.0.113.78").await.unwrap();
+
+        assert_eq!(first.ip_address, again.ip_address);
+        assert_ne!(first.ip_address, other.ip_address);
+        assert_eq!(first.ip_address.len(), 64);
+        assert!(!first.ip_address.contains("203.0.113"));
+        assert_eq!(first.pending_ip.as_deref(), Some("203.0.113.77"));
+
+        // 其他实例读取数据库中已有的盐
+        let restarted = IpAnonymizer::new(IpMode::Hash);
+        let after_restart = restarted.anonymize(&pool, "203.0.113.77").await.unwrap();
+        assert_eq!(first.ip_address, after_restart.ip_address);
+
+        // IPv4 映射地址与对应的 IPv4 地址相同
+        let mapped = anonymizer
+            .anonymize(&pool, "::ffff:203.0.113.77")
+            .await
+            .unwrap();
+        assert_eq!(first.ip_address, mapped.ip_address);
+    }
+
+    #[tokio::test]
+    async fn stores_address_according_to_mode() {
+        let pool = pool().await;
+        let stored = |mode, ip: &'static str| {
+            let pool = pool.clone();
+            async move { IpAnonymizer::new(mode).anonymize(&pool, ip).await.unwrap() }
+        };
+
+        let raw = stored(IpMode::Raw, "203.0.113.77").await;
+        assert_eq!(raw.ip_address, "203.0.113.77");
+        assert_eq!(raw.pending_ip, None);
+
+        let truncated = stored(IpMode::Truncate, "203.0.113.77").await;
+        assert_eq!(truncated.ip_address, "203.0.113.0");
+        assert_eq!(truncated.pending_ip.as_deref(), Some("203.0.113.77"));
+
+        for mode in [IpMode::Hash, IpMode::Truncate] {
+            let unknown = stored(mode, "not an ip").await;
+            assert_eq!(unknown.ip_address, "unknown");
+            assert_eq!(unknown.pending_ip, None);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! 后台汇总超过保留期限的访问记录，保留天数在项目中设置
+
+use time::{Duration, OffsetDateTime, Time};
+
+use crate::database::{self, DbPool};
+
+pub fn spawn(pool: DbPool, interval: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            run_once(&pool).await;
+        }
+    });
+}
+
+/// 依次处理设置了保留天数的项目，失败的项目留到下一轮
+async fn run_once(pool: &DbPool) {
+    let Ok(projects) = database::list_projects(pool).await else {
+        return;
+    };
+    let today = OffsetDateTime::now_utc().replace_time(Time::MIDNIGHT);
+
+    for project in projects {
+        let Some(days) = project.retention_days else {
+            continue;
+        };
+        let cutoff = today - Duration::days(days.into());
+
+        if let Ok(removed) = database::roll_up_visits(pool, &project, cutoff).await
+            && removed > 0
+        {
+            info!(
+                "项目`{}`中 {} 条超过 {} 天的访问记录已按天汇总",
+                project.slug, removed, days
+            );
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+
+use crate::client_ip::TrustedProxies;
+use crate::config::{BatchConfig, StatsConfig};
+use crate::database::DbPool;
+use crate::enrich::GeoEnricher;
+use crate::limit::TrackLimiter;
+use crate::privacy::IpAnonymizer;
+
+/// 管理接口使用的令牌
+#[derive(Clone)]
+pub struct AdminToken(pub Arc<str>);
+
+/// 统计接口鉴权使用的状态
+#[derive(Clone)]
+pub struct StatsAuth {
+    pub pool: DbPool,
+    /// 管理令牌同样可以读取统计
+    pub admin_token: Option<AdminToken>,
+}
+
+/// 路由共享的状态
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub pool: DbPool,
+    pub enricher: GeoEnricher,
+    pub stats: StatsConfig,
+    pub anonymizer: IpAnonymizer,
+    pub limiter: TrackLimiter,
+    pub trusted_proxies: TrustedProxies,
+    pub batch: BatchConfig,
+}
@@ -0,0 +1,67 @@
+//! 集成测试，数据库相关的测试总是在内存中的 SQLite 上执行，
+//! 设置了 `PT_TEST_POSTGRES_URL` 时同时在该 PostgreSQL 数据库上执行
+
+use sqlx::{AssertSqlSafe, PgPool, query};
+
+use crate::database::{self, DbPool};
+use crate::models::{NewProject, Project, ProjectSlug};
+use crate::privacy;
+
+mod router;
+mod storage;
+
+/// 测试使用的 PostgreSQL 数据库地址，如 `postgres://postgres@127.0.0.1/postgres`
+const POSTGRES_URL_VAR: &str = "PT_TEST_POSTGRES_URL";
+
+/// 对每个可用的数据库执行 `test`，每次都使用执行过迁移的空数据库
+///
+/// PostgreSQL 中每次测试使用单独的 schema，测试通过后删除
+pub async fn each_backend(test: impl AsyncFn(&DbPool)) {
+    let pool = database::init_database("sqlite::memory:")
+        .await
+        .expect("in-memory sqlite should initialize");
+    test(&pool).await;
+
+    let Ok(url) = std::env::var(POSTGRES_URL_VAR) else {
+        return;
+    };
+    let admin = PgPool::connect(&url)
+        .await
+        .expect("test postgres should be reachable");
+    let schema = format!("pt_test_{}", privacy::random_hex(8));
+    query(AssertSqlSafe(format!("CREATE SCHEMA {schema}")))
+        .execute(&admin)
+        .await
+        .expect("test schema should be created");
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let url = format!("{url}{separator}options=-c%20search_path%3D{schema}");
+    let pool = database::init_database(&url)
+        .await
+        .expect("test postgres should initialize");
+    test(&pool).await;
+
+    if let DbPool::Postgres(p) = pool {
+        p.close().await;
+    }
+    query(AssertSqlSafe(format!("DROP SCHEMA {schema} CASCADE")))
+        .execute(&admin)
+        .await
+        .expect("test schema should be dropped");
+}
+
+/// 登记一个测试项目
+pub async fn create_project(pool: &DbPool, slug: &str) -> Project {
+    let slug: ProjectSlug = slug.parse().expect("test slug should be valid");
+    let project = NewProject {
+        repository: format!("https://example.com/{}", slug),
+        icon: "icon.png".to_string(),
+        description: "test project".to_string(),
+        retention_days: None,
+    };
+
+    database::create_project(pool, &slug, &project)
+        .await
+        .expect("project should be created")
+        .expect("project should not exist yet")
+}
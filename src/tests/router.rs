@@ -0,0 +1,182 @@
+//! 通过完整的路由测试上报、地理位置补充和统计接口
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::http::{Method, Request, StatusCode, header};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+use crate::auth;
+use crate::client_ip::TrustedProxies;
+use crate::config::{BatchConfig, RateLimitConfig, StatsConfig};
+use crate::database::{self, DbPool};
+use crate::enrich::GeoEnricher;
+use crate::geo::{GeoInfo, PRIVATE, StaticResolver};
+use crate::limit::TrackLimiter;
+use crate::models::ProjectDetailedStats;
+use crate::privacy::{IpAnonymizer, IpMode};
+use crate::state::{AdminToken, AppState, StatsAuth};
+
+const ADMIN_TOKEN: &str = "admin-token";
+
+/// 使用内存中的 SQLite 和 `resolver` 构建路由，统计接口需要鉴权
+async fn app(resolver: StaticResolver) -> (Router, DbPool) {
+    let pool = database::init_database("sqlite::memory:")
+        .await
+        .expect("in-memory sqlite should initialize");
+    let admin_token = AdminToken(ADMIN_TOKEN.into());
+    let state = AppState {
+        pool: pool.clone(),
+        enricher: GeoEnricher::spawn(pool.clone(), Arc::new(resolver)),
+        stats: StatsConfig::default(),
+        anonymizer: IpAnonymizer::new(IpMode::default()),
+        limiter: TrackLimiter::spawn(pool.clone(), RateLimitConfig::default()),
+        trusted_proxies: TrustedProxies::new(&[], Default::default()),
+        batch: BatchConfig::default(),
+    };
+    let stats_auth = StatsAuth {
+        pool: pool.clone(),
+        admin_token: Some(admin_token.clone()),
+    };
+
+    (
+        crate::router(state, Some(stats_auth), Some(admin_token)),
+        pool,
+    )
+}
+
+async fn send(router: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    (status, body)
+}
+
+fn json_request(method: Method, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// 未经过可信代理时路由看不到对端地址，此时 `X-Forwarded-For` 决定访问者地址
+fn track_request(project: &str, ip: &str) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(format!("/track/{}", project))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("x-forwarded-for", ip)
+        .body(Body::from(json!({ "platform": "linux" }).to_string()))
+        .unwrap()
+}
+
+fn stats_request(project: &str, token: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder().uri(format!("/stats/{}", project));
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+async fn create_project(router: &Router, slug: &str) {
+    let project = json!({
+        "repository": format!("https://example.com/{}", slug),
+        "icon": "icon.png",
+        "description": "test project",
+    });
+    let request = json_request(Method::POST, &format!("/admin/projects/{}", slug), project);
+    let (status, _) = send(router, request).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+/// 等待后台任务补充完所有访问记录的地理位置
+///
+/// 统计中最先查询的是国家分布，其中没有待处理的记录时，之后查询的部分也不会有
+async fn enriched_stats(router: &Router, project: &str) -> ProjectDetailedStats {
+    for _ in 0..100 {
+        let (status, body) = send(router, stats_request(project, Some(ADMIN_TOKEN))).await;
+        assert_eq!(status, StatusCode::OK);
+        let stats: ProjectDetailedStats = serde_json::from_value(body).unwrap();
+        if stats.country_stats.iter().all(|c| c.country.is_some()) {
+            return stats;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("visits of `{}` were not enriched in time", project);
+}
+
+#[tokio::test]
+async fn tracked_visits_are_enriched_by_injected_resolver() {
+    let resolver = StaticResolver::new(GeoInfo::country("ZZ"))
+        .with("8.8.8.8".parse().unwrap(), GeoInfo::country("US"));
+    let (router, _) = app(resolver).await;
+    create_project(&router, "demo").await;
+
+    for ip in ["8.8.8.8", "9.9.9.9", "192.168.1.5"] {
+        let (status, body) = send(&router, track_request("demo", ip)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+    }
+
+    let stats = enriched_stats(&router, "demo").await;
+    assert_eq!((stats.total_visits, stats.unique_visitors), (3, 3));
+    let mut countries: Vec<_> = stats
+        .country_stats
+        .iter()
+        .map(|c| (c.country.clone().unwrap(), c.visit_count))
+        .collect();
+    countries.sort();
+    // 私有地址不经过查询服务
+    assert_eq!(
+        countries,
+        [
+            (PRIVATE.to_string(), 1),
+            ("US".to_string(), 1),
+            ("ZZ".to_string(), 1)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn tracking_unknown_or_archived_project_fails() {
+    let (router, _) = app(StaticResolver::default()).await;
+    create_project(&router, "demo").await;
+
+    let (status, _) = send(&router, track_request("missing", "8.8.8.8")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let archive = json_request(Method::DELETE, "/admin/projects/demo", Value::Null);
+    let (status, _) = send(&router, archive).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&router, track_request("demo", "8.8.8.8")).await;
+    assert_eq!(status, StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn stats_require_api_key_or_admin_token() {
+    let (router, pool) = app(StaticResolver::default()).await;
+    create_project(&router, "demo").await;
+    let (key, key_hash) = auth::generate_api_key();
+    database::create_api_key(&pool, "ci", &key_hash, None)
+        .await
+        .unwrap();
+
+    let (status, _) = send(&router, stats_request("demo", None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&router, stats_request("demo", Some("wrong"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    for token in [ADMIN_TOKEN, key.as_str()] {
+        let (status, body) = send(&router, stats_request("demo", Some(token))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["project_name"], "demo");
+    }
+}
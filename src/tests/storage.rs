@@ -0,0 +1,456 @@
+//! `database` 模块的查询在两种数据库上的行为
+
+use time::{Duration, OffsetDateTime, Time};
+
+use super::{create_project, each_backend};
+use crate::database::{self, DbPool};
+use crate::geo::GeoInfo;
+use crate::migrations;
+use crate::models::{
+    DropReason, NewEvent, NewProject, NewVisit, OrphanPolicy, Platform, Project, ProjectUpdate,
+};
+use crate::privacy::StoredIp;
+
+fn visit(platform: Platform, install_id: Option<&str>) -> NewVisit {
+    NewVisit {
+        platform,
+        app_version: None,
+        os_version: None,
+        arch: None,
+        install_id: install_id.map(str::to_string),
+        created_at: None,
+    }
+}
+
+fn at(mut visit: NewVisit, created_at: OffsetDateTime) -> NewVisit {
+    visit.created_at = Some(created_at);
+    visit
+}
+
+/// 以原始地址保存，写入 `visits`
+async fn track(pool: &DbPool, project: &Project, ip: &str, visits: &[NewVisit]) {
+    let ip = StoredIp {
+        ip_address: ip.to_string(),
+        pending_ip: None,
+    };
+    let visits: Vec<_> = visits.iter().map(|visit| (project, visit)).collect();
+    database::insert_visits(pool, &ip, &visits)
+        .await
+        .expect("visits should be inserted");
+}
+
+/// 按 IP 地址补充所有待处理记录的国家
+async fn enrich(pool: &DbPool, country_of: impl Fn(&str) -> &'static str) {
+    let pending = database::get_pending_geo_visits(pool, 100)
+        .await
+        .expect("pending visits should be listed");
+    for (id, ip) in pending {
+        database::update_visit_geo(pool, id, &GeoInfo::country(country_of(&ip)))
+            .await
+            .expect("geo should be updated");
+    }
+}
+
+fn midnight_utc(time: OffsetDateTime) -> OffsetDateTime {
+    time.replace_time(Time::MIDNIGHT)
+}
+
+#[tokio::test]
+async fn migrations_are_idempotent() {
+    each_backend(async |pool| {
+        let pending = migrations::run(pool, false).await.unwrap();
+        assert!(pending.is_empty());
+        assert_eq!(
+            migrations::current_version(pool).await.unwrap(),
+            migrations::latest_version()
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn project_updates_keep_unset_fields() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        let slug = project.slug.parse().unwrap();
+        let duplicate = NewProject {
+            repository: String::new(),
+            icon: String::new(),
+            description: String::new(),
+            retention_days: None,
+        };
+        assert!(
+            database::create_project(pool, &slug, &duplicate)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let update = |description: Option<&str>, archived, retention_days| ProjectUpdate {
+            repository: None,
+            icon: None,
+            description: description.map(str::to_string),
+            archived,
+            retention_days,
+        };
+
+        let updated = database::update_project(pool, &slug, &update(Some("new"), None, Some(30)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.description, "new");
+        assert_eq!(updated.repository, project.repository);
+        assert_eq!(updated.retention_days, Some(30));
+        assert!(!updated.is_archived());
+
+        let updated = database::update_project(pool, &slug, &update(None, Some(true), Some(0)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.description, "new");
+        assert_eq!(updated.retention_days, None);
+        assert!(updated.is_archived());
+
+        let updated = database::update_project(pool, &slug, &update(None, Some(false), None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!updated.is_archived());
+
+        let missing = "missing".parse().unwrap();
+        assert!(
+            database::update_project(pool, &missing, &update(None, None, None))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let projects = database::list_projects(pool).await.unwrap();
+        assert_eq!(projects.iter().filter(|p| p.slug == "demo").count(), 1);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn visit_stats_count_visitors_by_install_id_or_ip() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        let mut versioned = visit(Platform::Linux, Some("a"));
+        versioned.app_version = Some("1.2.0".to_string());
+        track(
+            pool,
+            &project,
+            "8.8.8.8",
+            &[
+                versioned,
+                visit(Platform::Linux, Some("a")),
+                visit(Platform::Windows, None),
+            ],
+        )
+        .await;
+        track(pool, &project, "1.1.1.1", &[visit(Platform::Windows, None)]).await;
+        enrich(pool, |ip| if ip == "8.8.8.8" { "US" } else { "AU" }).await;
+
+        let stats = database::get_project_stats(pool, &project).await.unwrap();
+        assert_eq!(stats.total_visits, 4);
+        // 安装标识 `a`、未上报安装标识的两个 IP 地址
+        assert_eq!(stats.unique_visitors, 3);
+
+        let countries = database::get_country_stats(pool, &project, None)
+            .await
+            .unwrap();
+        let countries: Vec<_> = countries
+            .iter()
+            .map(|c| (c.country.as_deref(), c.visit_count))
+            .collect();
+        assert_eq!(countries, [(Some("US"), 3), (Some("AU"), 1)]);
+
+        let platforms = database::get_platform_stats(pool, &project, None)
+            .await
+            .unwrap();
+        assert_eq!(platforms.len(), 2);
+        assert!(platforms.iter().all(|p| p.visit_count == 2));
+
+        let versions = database::get_version_stats(pool, &project, None)
+            .await
+            .unwrap();
+        let versions: Vec<_> = versions
+            .iter()
+            .map(|v| (v.app_version.as_deref(), v.visit_count, v.unique_visitors))
+            .collect();
+        assert_eq!(versions, [(None, 3, 3), (Some("1.2.0"), 1, 1)]);
+
+        let breakdown = database::get_platform_country_stats(pool, &project, None)
+            .await
+            .unwrap();
+        let windows = breakdown
+            .iter()
+            .find(|p| p.platform == Platform::Windows)
+            .unwrap();
+        assert_eq!(windows.countries.len(), 2);
+
+        let recent = database::get_recent_visits(pool, &project, 3)
+            .await
+            .unwrap();
+        assert_eq!(recent.len(), 3);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn range_and_series_stats_use_visit_time() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        let now = OffsetDateTime::now_utc();
+        let today = midnight_utc(now);
+        track(
+            pool,
+            &project,
+            "8.8.8.8",
+            &[
+                at(visit(Platform::Linux, Some("a")), today - Duration::days(3)),
+                at(visit(Platform::Linux, Some("a")), today - Duration::days(3)),
+                visit(Platform::Linux, Some("b")),
+            ],
+        )
+        .await;
+
+        let range = (today, today + Duration::DAY);
+        let stats = database::get_project_stats_in_range(pool, &project, range)
+            .await
+            .unwrap();
+        assert_eq!((stats.total_visits, stats.unique_visitors), (1, 1));
+
+        let buckets: Vec<_> = (0..4)
+            .map(|i| {
+                let start = today - Duration::days(3 - i);
+                (start, start + Duration::DAY)
+            })
+            .collect();
+        let series = database::get_project_series(pool, &project, &buckets)
+            .await
+            .unwrap();
+        assert_eq!(series, [(2, 1), (0, 0), (0, 0), (1, 1)]);
+
+        let all = database::get_all_projects_stats_in_range(pool, range, OrphanPolicy::Skip)
+            .await
+            .unwrap();
+        assert_eq!(all.projects.len(), 1);
+        assert_eq!(all.projects[0].total_visits, 1);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn roll_up_keeps_totals_and_skips_visits_awaiting_geo() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        let today = midnight_utc(OffsetDateTime::now_utc());
+        let old = today - Duration::days(10) + Duration::hours(12);
+        track(
+            pool,
+            &project,
+            "8.8.8.8",
+            &[
+                at(visit(Platform::Linux, Some("a")), old),
+                at(visit(Platform::Linux, Some("a")), old),
+                at(visit(Platform::Linux, Some("b")), old),
+            ],
+        )
+        .await;
+        enrich(pool, |_| "US").await;
+        track(
+            pool,
+            &project,
+            "1.1.1.1",
+            &[
+                at(visit(Platform::Linux, Some("c")), old),
+                visit(Platform::Linux, Some("a")),
+            ],
+        )
+        .await;
+
+        let removed = database::roll_up_visits(pool, &project, today)
+            .await
+            .unwrap();
+        assert_eq!(removed, 3);
+        assert_eq!(
+            database::roll_up_visits(pool, &project, today)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // 尚未补充地理位置的记录不汇总
+        let pending = database::get_pending_geo_visits(pool, 100).await.unwrap();
+        assert_eq!(pending.len(), 2);
+
+        let stats = database::get_project_stats(pool, &project).await.unwrap();
+        assert_eq!(stats.total_visits, 5);
+        // 汇总记录的独立访客数直接相加，`a` 被计算两次
+        assert_eq!(stats.unique_visitors, 4);
+
+        let countries = database::get_country_stats(pool, &project, None)
+            .await
+            .unwrap();
+        assert_eq!(countries[0].country.as_deref(), Some("US"));
+        assert_eq!(countries[0].visit_count, 3);
+
+        let buckets = [(today - Duration::days(10), today - Duration::days(9))];
+        let series = database::get_project_series(pool, &project, &buckets)
+            .await
+            .unwrap();
+        assert_eq!(series, [(4, 3)]);
+
+        let all = database::get_all_projects_stats(pool, OrphanPolicy::Skip)
+            .await
+            .unwrap();
+        assert_eq!(all.projects[0].total_visits, 5);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn orphaned_visits_follow_policy() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        let ghost = Project {
+            slug: "ghost".to_string(),
+            ..project.clone()
+        };
+        track(pool, &project, "8.8.8.8", &[visit(Platform::Linux, None)]).await;
+        track(pool, &ghost, "8.8.8.8", &[visit(Platform::Linux, None)]).await;
+
+        let skipped = database::get_all_projects_stats(pool, OrphanPolicy::Skip)
+            .await
+            .unwrap();
+        assert_eq!(skipped.projects.len(), 1);
+        assert!(skipped.orphaned.is_none());
+
+        let reported = database::get_all_projects_stats(pool, OrphanPolicy::Report)
+            .await
+            .unwrap();
+        let orphaned = reported.orphaned.unwrap();
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].project_name, "ghost");
+
+        let bucketed = database::get_all_projects_stats(pool, OrphanPolicy::Bucket)
+            .await
+            .unwrap();
+        assert_eq!(bucketed.orphaned.unwrap()[0].project_name, "orphaned");
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn api_keys_are_found_until_revoked() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        let slug = project.slug.parse().unwrap();
+        let key = database::create_api_key(pool, "ci", "hash", Some(&slug))
+            .await
+            .unwrap();
+        assert_eq!(key.project_name.as_deref(), Some("demo"));
+
+        let found = database::find_api_key(pool, "hash").await.unwrap().unwrap();
+        assert_eq!(found.id, key.id);
+        assert!(
+            database::find_api_key(pool, "other")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let revoked = database::revoke_api_key(pool, key.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(revoked.revoked_at.is_some());
+        assert!(
+            database::find_api_key(pool, "hash")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(database::list_api_keys(pool).await.unwrap().len(), 1);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn dropped_visits_accumulate_per_reason() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        for (reason, count) in [
+            (DropReason::RateLimited, 2),
+            (DropReason::RateLimited, 3),
+            (DropReason::Duplicate, 1),
+            (DropReason::EventRateLimited, 4),
+        ] {
+            database::record_dropped_visits(pool, &project.slug, reason, count)
+                .await
+                .unwrap();
+        }
+
+        let (visits, events) = database::get_dropped_visits(pool, &project).await.unwrap();
+        assert_eq!((visits.rate_limited, visits.duplicate, events), (5, 1, 4));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn events_count_by_name_and_rank_property_values() {
+    each_backend(async |pool| {
+        let project = create_project(pool, "demo").await;
+        let event = |name: &str, reason: Option<&str>| NewEvent {
+            name: name.to_string(),
+            properties: reason
+                .map(|reason| vec![("reason".to_string(), reason.to_string())])
+                .unwrap_or_default(),
+        };
+        for event in [
+            event("upload_failed", Some("timeout")),
+            event("upload_failed", Some("timeout")),
+            event("upload_failed", Some("http_500")),
+            event("wallpaper_changed", None),
+        ] {
+            database::insert_event(pool, &project, &event)
+                .await
+                .unwrap();
+        }
+
+        let counts = database::get_event_counts(pool, &project, None)
+            .await
+            .unwrap();
+        let counts: Vec<_> = counts.iter().map(|c| (c.name.as_str(), c.count)).collect();
+        assert_eq!(counts, [("upload_failed", 3), ("wallpaper_changed", 1)]);
+
+        let count = database::get_event_count(pool, &project, "upload_failed", None)
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+
+        let properties =
+            database::get_event_property_stats(pool, &project, "upload_failed", None, 1)
+                .await
+                .unwrap();
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties[0].key, "reason");
+        let values: Vec<_> = properties[0]
+            .values
+            .iter()
+            .map(|v| (v.value.as_str(), v.count))
+            .collect();
+        assert_eq!(values, [("timeout", 2)]);
+
+        let today = midnight_utc(OffsetDateTime::now_utc());
+        let buckets = [
+            (today - Duration::DAY, today),
+            (today, today + Duration::DAY),
+        ];
+        let series = database::get_event_series(pool, &project, "upload_failed", &buckets)
+            .await
+            .unwrap();
+        assert_eq!(series, [0, 3]);
+    })
+    .await;
+}